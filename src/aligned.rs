@@ -0,0 +1,212 @@
+//! Rows starting at multiples of the row width, used when `Layout::aligned` is set. Segment
+//! boundaries are marked inside the rows and the tags of the segments starting in a row are
+//! listed in a legend next to it.
+
+use std::cmp;
+
+use {format_value, is_unknown_value};
+use {CharSet, Filter, Layout, Segment, SegmentKind, Style, Theme, Ty};
+
+/// Part of a row printed in a single style
+pub struct Span {
+    pub style: Option<Style>,
+    pub text: String,
+}
+
+pub struct Row {
+    pub offset: usize,
+    pub hex: Vec<Span>,
+    pub text: Vec<Span>,
+    /// Tags and values of the segments starting in this row
+    pub legend: Vec<Span>,
+}
+
+/// Segment without children, with the absolute range and the style and text cells of its bytes
+pub(crate) struct Leaf<'a> {
+    pub(crate) begin: usize,
+    pub(crate) end: usize,
+    seg: &'a Segment,
+    pub(crate) style: Option<Style>,
+    pub(crate) cells: Vec<char>,
+}
+
+pub(crate) fn push(spans: &mut Vec<Span>, style: Option<Style>, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    if let Some(last) = spans.last_mut() {
+        if last.style == style {
+            last.text.push_str(text);
+            return;
+        }
+    }
+    spans.push(Span { style, text: text.to_string() });
+}
+
+pub(crate) fn collect_leaves<'a>(seg: &'a Segment, len: usize, buf: &[u8], charset: CharSet, theme: &Theme, leaves: &mut Vec<Leaf<'a>>) {
+    let seg_buf = &buf[seg.start..seg.start + len];
+    let childs = seg.expand(seg_buf);
+    if childs.is_empty() {
+        let style = match seg.kind {
+            SegmentKind::Line { .. } if is_unknown_value(&seg.ty, seg_buf) => Some(theme.error),
+            SegmentKind::Line { paint, .. } => Some(theme.resolve(paint)),
+            _ => None,
+        };
+        let cells = match seg.ty {
+            Ty::Text(encoding) => encoding.cells(seg_buf, len),
+            _ => charset.cells(seg_buf, len),
+        };
+        leaves.push(Leaf { begin: seg.start, end: seg.start + len, seg, style, cells });
+        return;
+    }
+    for (&(begin, end), child) in childs {
+        collect_leaves(child, end - begin, buf, charset, theme, leaves);
+    }
+}
+
+/// Leaf containing `pos`. `next` is a cursor into `leaves` which only moves forward.
+pub(crate) fn leaf_at<'a, 'b>(leaves: &'b [Leaf<'a>], next: &mut usize, pos: usize) -> Option<&'b Leaf<'a>> {
+    while *next < leaves.len() && leaves[*next].end <= pos {
+        *next += 1;
+    }
+    leaves.get(*next).filter(|leaf| leaf.begin <= pos)
+}
+
+/// Cells are formatted with the format of the layout, segments can't override it in this mode.
+/// Rows overlapping the segments selected by `filter` are printed whole.
+pub fn for_each_row<F: FnMut(Row)>(buf: &[u8], main: &Segment, layout: &Layout, charset: CharSet, theme: &Theme, filter: &Filter, mut f: F) {
+    let mut leaves = Vec::new();
+    collect_leaves(main, buf.len(), buf, charset, theme, &mut leaves);
+    let selected = filter.top_ranges(buf, main);
+
+    let row_len = layout.row_len();
+    let format = layout.cell;
+    let cell_len = format.bytes();
+    let mut next_hex = 0;
+    let mut next_text = 0;
+    for row_start in (0..buf.len()).step_by(row_len) {
+        let row_end = cmp::min(row_start + row_len, buf.len());
+        if !filter.overlaps(row_start, row_end) || !selected.iter().any(|range| range.start < row_end && row_start < range.end) {
+            continue;
+        }
+        let mut row = Row { offset: row_start, hex: Vec::new(), text: Vec::new(), legend: Vec::new() };
+
+        for pos in (row_start..row_end).step_by(cell_len) {
+            let i = pos - row_start;
+            let end = cmp::min(pos + cell_len, row_end);
+            let leaf = leaf_at(&leaves, &mut next_hex, pos);
+            let style = leaf.and_then(|leaf| leaf.style);
+            // Separators inside a segment get its style to keep the number of spans low
+            let inner_style = if leaf.is_some_and(|leaf| leaf.begin == pos) { None } else { style };
+            push(&mut row.hex, inner_style, layout.hex_separator(i));
+            push(&mut row.hex, style, &layout.cell(format, &buf[pos..end]));
+            let boundary = end < row_end
+                && (leaf.is_some_and(|leaf| leaf.end == end) || leaves.get(next_hex).is_some_and(|leaf| leaf.begin == end));
+            if boundary {
+                push(&mut row.hex, None, "|");
+            } else {
+                push(&mut row.hex, style, " ");
+            }
+        }
+        for i in ((row_end - row_start).div_ceil(cell_len) * cell_len..row_len).step_by(cell_len) {
+            push(&mut row.hex, None, layout.hex_separator(i));
+            push(&mut row.hex, None, &".".repeat(format.width()));
+            push(&mut row.hex, None, " ");
+        }
+
+        for (i, pos) in (row_start..row_end).enumerate() {
+            let leaf = leaf_at(&leaves, &mut next_text, pos);
+            let style = leaf.and_then(|leaf| leaf.style);
+            let inner_style = if leaf.is_some_and(|leaf| leaf.begin == pos) { None } else { style };
+
+            if let Some(leaf) = leaf.filter(|leaf| leaf.begin == pos) {
+                if let SegmentKind::Line { ref tag, .. } = leaf.seg.kind {
+                    if !row.legend.is_empty() {
+                        push(&mut row.legend, None, "  ");
+                    }
+                    let value = format_value(&leaf.seg.ty, &buf[leaf.begin..leaf.end]);
+                    match value {
+                        Some(value) if matches!(leaf.seg.ty, Ty::Text(_)) => push(&mut row.legend, style, &format!("{}: {:?}", tag, value)),
+                        Some(value) => push(&mut row.legend, style, &format!("{}: {}", tag, value)),
+                        None => push(&mut row.legend, style, tag),
+                    }
+                }
+            }
+
+            let cell = match leaf {
+                Some(leaf) => leaf.cells[pos - leaf.begin],
+                None => charset.cells(&buf[pos..], 1)[0],
+            };
+            push(&mut row.text, inner_style, layout.text_separator(i));
+            push(&mut row.text, style, &cell.to_string());
+        }
+        for i in row_end - row_start..row_len {
+            push(&mut row.text, None, layout.text_separator(i));
+            push(&mut row.text, None, ".");
+        }
+        f(row);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {Color, Encoding, Select, StyleBuilder};
+
+    /// Span texts of the hex, text and legend column of every row
+    type Spans = (usize, Vec<String>, Vec<String>, Vec<String>);
+
+    fn rows(buf: &[u8], filter: &Filter) -> Vec<Spans> {
+        let mut main = Segment::new(0, Ty::Binary, SegmentKind::Main);
+        {
+            let mut builder = StyleBuilder::new(buf, 0, &mut main.childs);
+            {
+                let mut block = builder.block(0, 6, Ty::Binary);
+                block.set_color(Color::Red);
+                block.line(3, Ty::Ascii, "s");
+                // Crosses the end of the first row
+                block.set_color(Color::Green);
+                block.line(2, Ty::BeNum, "n");
+                block.set_color(Color::Red);
+                block.line(1, Ty::Binary, "b");
+            }
+            builder.block(6, 9, Ty::Binary).line(3, Ty::Text(Encoding::Utf8), "t");
+        }
+        let layout = Layout { bytes_per_row: 4, group_size: 2, offsets: None, ..Layout::default() };
+        let texts = |spans: Vec<Span>| spans.into_iter().map(|span| span.text).collect::<Vec<_>>();
+        let mut rows = Vec::new();
+        for_each_row(buf, &main, &layout, CharSet::Ascii, &Theme::default(), filter, |row| {
+            rows.push((row.offset, texts(row.hex), texts(row.text), texts(row.legend)));
+        });
+        rows
+    }
+
+    fn strings(texts: &[&str]) -> Vec<String> {
+        texts.iter().map(|text| text.to_string()).collect()
+    }
+
+    #[test]
+    fn all_rows() {
+        let rows = rows(b"abc\x01\x02\x03xyz", &Filter::default());
+        assert_eq!(rows, vec![
+            (0, strings(&[" ", "61 62  63", "|", "01 "]), strings(&["ab c", "\u{FFFD}"]), strings(&["s", "  ", "n: 258"])),
+            // The line started in the previous row has no legend, the row ends at the boundary
+            // of the next segment
+            (4, strings(&[" 02", "|", "03", "| ", "78 79 "]), strings(&["\u{FFFD}", "\u{FFFD}", " ", "xy"]), strings(&["b", "  ", "t: \"xyz\""])),
+            // The last row is padded
+            (8, strings(&[" 7A ", "..  .. .. "]), strings(&["z", ". .."]), vec![]),
+        ]);
+    }
+
+    #[test]
+    fn filtered_rows() {
+        let buf = b"abc\x01\x02\x03xyz";
+        let offsets = |filter: &Filter| rows(buf, filter).into_iter().map(|row| row.0).collect::<Vec<_>>();
+        // Rows overlapping the range are printed whole
+        assert_eq!(offsets(&Filter { range: Some(1..2), select: Vec::new() }), vec![0]);
+        assert_eq!(offsets(&Filter { range: Some(7..100), select: Vec::new() }), vec![4, 8]);
+        // As are rows overlapping the selected blocks
+        let select = vec![Select::Blocks(1..2)];
+        assert_eq!(offsets(&Filter { range: None, select }), vec![4, 8]);
+    }
+}
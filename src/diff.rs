@@ -0,0 +1,511 @@
+//! Comparison of two files, both styled by the same styler: side by side by their bytes or by
+//! the decoded fields.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::iter;
+use std::ops::Range;
+
+use aligned::{self, Leaf};
+use {CellFormat, CharSet, ColorSupport, Layout, SegmentRef, Style, StyleBuilder, TermPrinter, Theme};
+
+/// Part of the alignment of two buffers. Hunks alternate between same and changed bytes.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Hunk {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+    /// Both ranges contain the same bytes
+    pub same: bool,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Edit {
+    Same,
+    Delete,
+    Insert,
+}
+
+/// Edits searched for in each direction before `conquer` gives up on finding the middle snake
+const MAX_COST: isize = 256;
+
+/// Furthest reaching x per diagonal `k`, indexed from `-MAX_COST - 1`
+struct V(Vec<isize>);
+
+impl V {
+    fn new() -> V {
+        V(vec![0; 2 * MAX_COST as usize + 3])
+    }
+
+    fn at(&self, k: isize) -> isize {
+        self.0[(k + MAX_COST + 1) as usize]
+    }
+
+    fn set(&mut self, k: isize, x: isize) {
+        self.0[(k + MAX_COST + 1) as usize] = x;
+    }
+}
+
+/// Start of the middle snake of a shortest edit script, searching from both ends at once.
+/// `None` when the script takes more than about `2 * MAX_COST` edits.
+fn middle_snake(old: &[u8], new: &[u8], vf: &mut V, vb: &mut V) -> Option<(usize, usize)> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let delta = n - m;
+    let odd = delta % 2 != 0;
+    vf.set(1, 0);
+    vb.set(1, 0);
+    for d in 0..cmp::min((n + m + 1) / 2 + 1, MAX_COST) {
+        for k in (-d..=d).rev().step_by(2) {
+            let mut x = if k == -d || (k != d && vf.at(k - 1) < vf.at(k + 1)) { vf.at(k + 1) } else { vf.at(k - 1) + 1 };
+            let (x0, y0) = (x, x - k);
+            let mut y = y0;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            vf.set(k, x);
+            if odd && (k - delta).abs() < d && x + vb.at(delta - k) >= n {
+                return Some((x0 as usize, y0 as usize));
+            }
+        }
+        // Backward paths count x and y from the ends
+        for k in (-d..=d).rev().step_by(2) {
+            let mut x = if k == -d || (k != d && vb.at(k - 1) < vb.at(k + 1)) { vb.at(k + 1) } else { vb.at(k - 1) + 1 };
+            let mut y = x - k;
+            while x < n && y < m && old[(n - x - 1) as usize] == new[(m - y - 1) as usize] {
+                x += 1;
+                y += 1;
+            }
+            vb.set(k, x);
+            if !odd && (k - delta).abs() <= d && x + vf.at(delta - k) >= n {
+                return Some(((n - x) as usize, (m - y) as usize));
+            }
+        }
+    }
+    None
+}
+
+/// Appends the edits turning `old` into `new` by the linear space variant of the Myers
+/// algorithm. Parts which differ in too many places are split in the middle instead, so the
+/// script may be longer than needed for very different files.
+fn conquer(old: &[u8], new: &[u8], vf: &mut V, vb: &mut V, edits: &mut Vec<Edit>) {
+    let prefix = old.iter().zip(new).take_while(|&(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|&(a, b)| a == b).count();
+    edits.extend(iter::repeat_n(Edit::Same, prefix));
+    let (old, new) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+    if old.is_empty() {
+        edits.extend(iter::repeat_n(Edit::Insert, new.len()));
+    } else if new.is_empty() {
+        edits.extend(iter::repeat_n(Edit::Delete, old.len()));
+    } else {
+        let (x, y) = middle_snake(old, new, vf, vb).unwrap_or((old.len() / 2, new.len() / 2));
+        conquer(&old[..x], &new[..y], vf, vb, edits);
+        conquer(&old[x..], &new[y..], vf, vb, edits);
+    }
+    edits.extend(iter::repeat_n(Edit::Same, suffix));
+}
+
+/// Aligns `new` to `old`, inserted and deleted bytes shift the following bytes instead of making
+/// them differ
+pub fn diff(old: &[u8], new: &[u8]) -> Vec<Hunk> {
+    let mut edits = Vec::with_capacity(cmp::max(old.len(), new.len()));
+    conquer(old, new, &mut V::new(), &mut V::new(), &mut edits);
+
+    let mut hunks: Vec<Hunk> = Vec::new();
+    let (mut x, mut y) = (0, 0);
+    for edit in edits {
+        let same = edit == Edit::Same;
+        if hunks.last().is_none_or(|hunk| hunk.same != same) {
+            hunks.push(Hunk { old: x..x, new: y..y, same });
+        }
+        let hunk = hunks.last_mut().unwrap();
+        if edit != Edit::Insert {
+            x += 1;
+            hunk.old.end = x;
+        }
+        if edit != Edit::Delete {
+            y += 1;
+            hunk.new.end = y;
+        }
+    }
+    hunks
+}
+
+/// Segment only in one of the trees or with different bytes in both
+pub struct Change<'a> {
+    /// Path of the segment in the tree, see `SegmentRef::lookup`
+    pub path: String,
+    /// `None` for added segments
+    pub old: Option<SegmentRef<'a>>,
+    /// `None` for removed segments
+    pub new: Option<SegmentRef<'a>>,
+}
+
+/// Children with their path components. The index is left out for the first sibling of a name.
+fn named_childs(seg: SegmentRef) -> Vec<(String, SegmentRef)> {
+    let mut counts = HashMap::new();
+    seg.childs()
+        .map(|child| {
+            let count = counts.entry(child.name()).or_insert(0);
+            *count += 1;
+            match *count {
+                1 => (child.name().to_string(), child),
+                n => (format!("{}[{}]", child.name(), n - 1), child),
+            }
+        })
+        .collect()
+}
+
+fn diff_childs<'a>(old: SegmentRef<'a>, new: SegmentRef<'a>, prefix: &str, changes: &mut Vec<Change<'a>>) {
+    let path = |name: &str| if prefix.is_empty() { name.to_string() } else { format!("{}/{}", prefix, name) };
+    let new_childs = named_childs(new);
+    let mut unpaired = new_childs.iter().cloned().collect::<HashMap<_, _>>();
+    for (name, old) in named_childs(old) {
+        let new = match unpaired.remove(&name) {
+            Some(new) => new,
+            None => {
+                changes.push(Change { path: path(&name), old: Some(old), new: None });
+                continue;
+            }
+        };
+        match (old.childs().next().is_some(), new.childs().next().is_some()) {
+            (true, true) => diff_childs(old, new, &path(&name), changes),
+            (false, false) if old.bytes() == new.bytes() => {}
+            _ => changes.push(Change { path: path(&name), old: Some(old), new: Some(new) }),
+        }
+    }
+    // Added in the order of the new tree
+    for (name, new) in new_childs {
+        if unpaired.remove(&name).is_some() {
+            changes.push(Change { path: path(&name), old: None, new: Some(new) });
+        }
+    }
+}
+
+/// Pairs the segments of both trees by their path and returns the segments which were added,
+/// removed or whose bytes differ. Headers and blocks in both trees are compared by their
+/// children, segments only in one tree are reported without their children.
+pub fn diff_trees<'a>(old: SegmentRef<'a>, new: SegmentRef<'a>) -> Vec<Change<'a>> {
+    let mut changes = Vec::new();
+    diff_childs(old, new, "", &mut changes);
+    changes
+}
+
+/// Formats a change like `~ block[2]/dst port: 80 -> 443`, `- block[5]` or `+ block[6]`
+fn format_change(change: &Change) -> String {
+    let value = |seg: SegmentRef| seg.value().map(|value| format!(": {}", value)).unwrap_or_default();
+    match (change.old, change.new) {
+        (Some(old), Some(new)) => match (old.value(), new.value()) {
+            (Some(old), Some(new)) => format!("~ {}: {} -> {}", change.path, old, new),
+            _ => format!("~ {}", change.path),
+        },
+        (Some(old), None) => format!("- {}{}", change.path, value(old)),
+        (None, Some(new)) => format!("+ {}{}", change.path, value(new)),
+        (None, None) => unreachable!(),
+    }
+}
+
+/// Prints every change on its own line, see `format_change`
+pub fn print_changes(changes: &[Change]) {
+    for change in changes {
+        println!("{}", format_change(change));
+    }
+}
+
+/// Lines ordered by offset with their absolute ranges
+fn lines(root: SegmentRef) -> Vec<(Range<usize>, SegmentRef)> {
+    root.iter().filter(|seg| seg.tag().is_some() && seg.childs().next().is_none()).map(|seg| (seg.range(), seg)).collect()
+}
+
+/// Lines overlapping `range`, or containing it when it is empty
+fn lines_in<'a>(lines: &[(Range<usize>, SegmentRef<'a>)], range: &Range<usize>) -> Vec<SegmentRef<'a>> {
+    let first = lines.partition_point(|line| line.0.end <= range.start);
+    lines[first..].iter().take_while(|line| line.0.start < range.end).map(|line| line.1).collect()
+}
+
+fn describe(seg: SegmentRef) -> String {
+    match seg.value() {
+        Some(value) => format!("{}: {}", seg.tag().unwrap(), value),
+        None => seg.tag().unwrap().to_string(),
+    }
+}
+
+pub struct DiffPrinter<A: AsRef<[u8]> = Vec<u8>, B: AsRef<[u8]> = Vec<u8>> {
+    old: TermPrinter<A>,
+    new: TermPrinter<B>,
+    context: usize,
+}
+
+impl<A: AsRef<[u8]>, B: AsRef<[u8]>> DiffPrinter<A, B> {
+    /// Rows are 16 bytes wide by default to fit both files next to each other
+    pub fn new(old: A, new: B) -> Self {
+        let mut printer = DiffPrinter { old: TermPrinter::new(old), new: TermPrinter::new(new), context: 2 };
+        printer.set_layout(Layout { bytes_per_row: 16, ..Layout::default() });
+        printer
+    }
+
+    pub fn set_charset(&mut self, charset: CharSet) {
+        self.old.set_charset(charset);
+        self.new.set_charset(charset);
+    }
+
+    /// Cells of more than one byte are shown as hex bytes, as aligned bytes may differ between
+    /// the files. Aligned rows are not supported.
+    pub fn set_layout(&mut self, mut layout: Layout) {
+        if layout.cell.bytes() != 1 {
+            layout.cell = CellFormat::Hex;
+        }
+        self.old.set_layout(layout.clone());
+        self.new.set_layout(layout);
+    }
+
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.old.set_theme(theme.clone());
+        self.new.set_theme(theme);
+    }
+
+    pub fn set_color_support(&mut self, color_support: ColorSupport) {
+        self.old.set_color_support(color_support);
+        self.new.set_color_support(color_support);
+    }
+
+    /// Number of rows shown at both ends of unchanged parts, the rows between them are left out
+    pub fn set_context(&mut self, context: usize) {
+        self.context = context;
+    }
+
+    /// Styles both files with `styler`
+    pub fn style<F: Fn(StyleBuilder)>(&mut self, styler: F) {
+        styler(self.old.style_builder());
+        styler(self.new.style_builder());
+    }
+
+    /// Fields which differ between both files, see `diff_trees`
+    pub fn changes(&self) -> Vec<Change<'_>> {
+        diff_trees(self.old.root(), self.new.root())
+    }
+
+    /// Offset, hex and text columns of a row of one side. `start` is `None` for an empty row.
+    fn side_row<X: AsRef<[u8]>>(printer: &TermPrinter<X>, leaves: &[Leaf], next: &mut usize, start: Option<usize>, len: usize, changed: Option<Style>) {
+        let layout = &printer.layout;
+        let buf = printer.buf.as_ref();
+        let (start, row) = match start {
+            Some(start) => (start, &buf[start..start + len]),
+            None => {
+                let width = layout.hex_row_width(layout.cell) + layout.text_row(&[]).chars().count() + 4;
+                print!("{}{}", layout.offset_padding(), " ".repeat(width));
+                return;
+            }
+        };
+        let mut hex = Vec::new();
+        let mut text = Vec::new();
+        let cells = printer.charset.cells(&buf[start..], len);
+        for i in 0..layout.bytes_per_row {
+            aligned::push(&mut hex, None, layout.hex_separator(i));
+            aligned::push(&mut text, None, layout.text_separator(i));
+            match (row.get(i), cells.get(i)) {
+                (Some(&b), Some(&cell)) => {
+                    let style = changed.or_else(|| aligned::leaf_at(leaves, next, start + i).and_then(|leaf| leaf.style));
+                    aligned::push(&mut hex, style, &layout.cell(layout.cell, &[b]));
+                    aligned::push(&mut text, style, &cell.to_string());
+                }
+                _ => {
+                    aligned::push(&mut hex, None, &".".repeat(layout.cell.width()));
+                    aligned::push(&mut text, None, ".");
+                }
+            }
+            aligned::push(&mut hex, None, " ");
+        }
+        print!("{}", layout.offset_column(start));
+        printer.print_spans(&hex);
+        print!("| ");
+        printer.print_spans(&text);
+        print!(" |");
+    }
+
+    /// Prints the rows `rows` of a hunk, counted from its start
+    fn print_rows(&self, hunk: &Hunk, rows: Range<usize>, old_leaves: &[Leaf], new_leaves: &[Leaf], old_next: &mut usize, new_next: &mut usize) {
+        let row_len = self.old.layout.row_len();
+        let (removed, added) = if hunk.same { (None, None) } else { (Some(self.old.theme.removed), Some(self.new.theme.added)) };
+        for row in rows {
+            let side = |range: &Range<usize>| {
+                let start = range.start + row * row_len;
+                if start < range.end { (Some(start), cmp::min(row_len, range.end - start)) } else { (None, 0) }
+            };
+            let (old_start, old_len) = side(&hunk.old);
+            let (new_start, new_len) = side(&hunk.new);
+            Self::side_row(&self.old, old_leaves, old_next, old_start, old_len, removed);
+            print!("   ");
+            Self::side_row(&self.new, new_leaves, new_next, new_start, new_len, added);
+            println!();
+        }
+    }
+
+    /// Lists the fields in the changed bytes of both files, pairing fields with the same tag
+    fn print_fields(&self, old_lines: Vec<SegmentRef>, new_lines: Vec<SegmentRef>) {
+        let mut new_lines = new_lines.into_iter().map(Some).collect::<Vec<_>>();
+        for old in old_lines {
+            let paired = new_lines.iter_mut().find(|new| new.is_some_and(|new| new.tag() == old.tag())).and_then(Option::take);
+            match paired {
+                Some(new) if new.bytes() == old.bytes() => {}
+                Some(new) => match new.value() {
+                    Some(value) => println!("{}~ {} -> {}", self.old.layout.offset_padding(), describe(old), value),
+                    None => println!("{}~ {}", self.old.layout.offset_padding(), describe(old)),
+                },
+                None => println!("{}- {}", self.old.layout.offset_padding(), describe(old)),
+            }
+        }
+        for new in new_lines.into_iter().flatten() {
+            println!("{}+ {}", self.old.layout.offset_padding(), describe(new));
+        }
+    }
+
+    pub fn print(self) {
+        assert!(!self.old.layout.aligned, "Diffs can't use aligned rows");
+        let (old_buf, new_buf) = (self.old.buf.as_ref(), self.new.buf.as_ref());
+        let mut old_leaves = Vec::new();
+        let mut new_leaves = Vec::new();
+        aligned::collect_leaves(&self.old.main, old_buf.len(), old_buf, self.old.charset, &self.old.theme, &mut old_leaves);
+        aligned::collect_leaves(&self.new.main, new_buf.len(), new_buf, self.new.charset, &self.new.theme, &mut new_leaves);
+        let old_lines = lines(self.old.root());
+        let new_lines = lines(self.new.root());
+
+        let row_len = self.old.layout.row_len();
+        let (mut old_next, mut new_next) = (0, 0);
+        for hunk in diff(old_buf, new_buf) {
+            let rows = cmp::max(hunk.old.len(), hunk.new.len()).div_ceil(row_len);
+            if hunk.same && rows > 2 * self.context + 1 {
+                self.print_rows(&hunk, 0..self.context, &old_leaves, &new_leaves, &mut old_next, &mut new_next);
+                println!("{}...", self.old.layout.offset_padding());
+                self.print_rows(&hunk, rows - self.context..rows, &old_leaves, &new_leaves, &mut old_next, &mut new_next);
+            } else {
+                self.print_rows(&hunk, 0..rows, &old_leaves, &new_leaves, &mut old_next, &mut new_next);
+            }
+            if !hunk.same {
+                self.print_fields(lines_in(&old_lines, &hunk.old), lines_in(&new_lines, &hunk.new));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tests::tree;
+
+    /// Checks that the hunks cover both buffers and that same hunks match, returns the number
+    /// of changed bytes
+    fn check(old: &[u8], new: &[u8]) -> usize {
+        let hunks = diff(old, new);
+        let (mut x, mut y, mut changed) = (0, 0, 0);
+        for (i, hunk) in hunks.iter().enumerate() {
+            assert_eq!((hunk.old.start, hunk.new.start), (x, y));
+            assert!(i == 0 || hunk.same != hunks[i - 1].same);
+            if hunk.same {
+                assert_eq!(old[hunk.old.clone()], new[hunk.new.clone()]);
+            } else {
+                changed += hunk.old.len() + hunk.new.len();
+            }
+            x = hunk.old.end;
+            y = hunk.new.end;
+        }
+        assert_eq!((x, y), (old.len(), new.len()));
+        changed
+    }
+
+    fn lcs(old: &[u8], new: &[u8]) -> usize {
+        let mut row = vec![0; new.len() + 1];
+        for &a in old {
+            let mut diagonal = 0;
+            for (j, &b) in new.iter().enumerate() {
+                let above = row[j + 1];
+                row[j + 1] = if a == b { diagonal + 1 } else { cmp::max(row[j], above) };
+                diagonal = above;
+            }
+        }
+        row[new.len()]
+    }
+
+    /// Bytes of a small alphabet, so that there are many equal bytes to align
+    fn random(seed: &mut u64, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                *seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                b'a' + (*seed >> 33) as u8 % 4
+            })
+            .collect()
+    }
+
+    fn hunk(old: Range<usize>, new: Range<usize>, same: bool) -> Hunk {
+        Hunk { old, new, same }
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(diff(b"", b""), vec![]);
+        assert_eq!(diff(b"", b"abc"), vec![hunk(0..0, 0..3, false)]);
+        assert_eq!(diff(b"abc", b""), vec![hunk(0..3, 0..0, false)]);
+        assert_eq!(diff(b"abc", b"abc"), vec![hunk(0..3, 0..3, true)]);
+    }
+
+    #[test]
+    fn insert() {
+        assert_eq!(diff(b"abcdef", b"abcXYdef"), vec![hunk(0..3, 0..3, true), hunk(3..3, 3..5, false), hunk(3..6, 5..8, true)]);
+    }
+
+    #[test]
+    fn delete() {
+        assert_eq!(diff(b"abcXYdef", b"abcdef"), vec![hunk(0..3, 0..3, true), hunk(3..5, 3..3, false), hunk(5..8, 3..6, true)]);
+    }
+
+    #[test]
+    fn shortest() {
+        let mut seed = 1;
+        for _ in 0..200 {
+            let (old_len, new_len) = (seed as usize % 40, (seed >> 8) as usize % 40);
+            let old = random(&mut seed, old_len);
+            let new = random(&mut seed, new_len);
+            assert_eq!(check(&old, &new), old.len() + new.len() - 2 * lcs(&old, &new));
+        }
+    }
+
+    #[test]
+    fn many_changes() {
+        let mut seed = 2;
+        let old = random(&mut seed, 100_000);
+        let mut new = old.clone();
+        for i in (0..new.len()).step_by(100) {
+            new[i] = b'z';
+        }
+        new.insert(50_000, b'z');
+        // Too many changes for the middle snake, splitting in the middle may add a few edits
+        assert!(check(&old, &new) <= 2 * 1000 + 1 + 4);
+        check(&old, &random(&mut seed, 100_000));
+    }
+
+    fn tree_changes(old: &[u8], new: &[u8]) -> Vec<String> {
+        let (old_main, new_main) = (tree(old, 2, 4), tree(new, 2, 4));
+        let changes = diff_trees(SegmentRef::new(old, &old_main), SegmentRef::new(new, &new_main));
+        changes.iter().map(format_change).collect()
+    }
+
+    #[test]
+    fn names() {
+        let buf = [0, 1, 0, 2, 0, 3, 0, 4, 0, 5];
+        let main = tree(&buf, 2, 4);
+        let names = named_childs(SegmentRef::new(&buf, &main)).into_iter().map(|(name, _)| name).collect::<Vec<_>>();
+        assert_eq!(names, vec!["header", "block", "block[1]"]);
+        let block = SegmentRef::new(&buf, &main).lookup("block[1]").unwrap();
+        let names = named_childs(block).into_iter().map(|(name, _)| name).collect::<Vec<_>>();
+        assert_eq!(names, vec!["port", "port[1]"]);
+    }
+
+    #[test]
+    fn trees() {
+        let old = [0, 1, 0, 2, 0, 3, 0, 4, 0, 5, 0, 6, 0, 7];
+        let new = [0, 1, 0, 2, 0, 3, 0, 4, 0, 9];
+        assert_eq!(tree_changes(&old, &old), Vec::<String>::new());
+        assert_eq!(tree_changes(&old, &new), vec!["~ block[1]/port[1]: 5 -> 9", "- block[2]"]);
+        assert_eq!(tree_changes(&new, &old), vec!["~ block[1]/port[1]: 9 -> 5", "+ block[2]"]);
+        assert_eq!(tree_changes(&old[..2], &new), vec!["+ block", "+ block[1]"]);
+    }
+}
@@ -0,0 +1,167 @@
+//! Selection of the part of the file which is printed. Offsets in the output stay absolute.
+
+use std::iter;
+use std::ops::Range;
+
+use search::value_is;
+use {Segment, SegmentKind, SegmentRef};
+
+/// Selects top-level headers and blocks
+#[derive(Clone)]
+pub enum Select {
+    /// Blocks by their index among the top-level blocks matched by the `Value` selections of
+    /// the filter, like packets among the blocks of a capture. The header isn't counted.
+    Blocks(Range<usize>),
+    /// Headers and blocks with a descendant at `path` having the value `value`, see
+    /// `SegmentRef::lookup`. Named values like `IPv6 (0x86DD)` also match by their name alone.
+    Value(String, String),
+}
+
+impl Select {
+    /// Parses `120..130` as blocks and `path=value` as a value
+    pub fn from_arg(arg: &str) -> Option<Select> {
+        if let Some(eq) = arg.find('=') {
+            return Some(Select::Value(arg[..eq].to_string(), arg[eq + 1..].to_string()));
+        }
+        let dots = arg.find("..")?;
+        let start = arg[..dots].parse().ok()?;
+        let end = arg[dots + 2..].parse().ok()?;
+        Some(Select::Blocks(start..end))
+    }
+
+    fn matches(&self, seg: SegmentRef, index: Option<usize>) -> bool {
+        match *self {
+            Select::Blocks(ref blocks) => index.is_some_and(|index| blocks.contains(&index)),
+            Select::Value(ref path, ref value) => {
+                let found = seg.lookup(path).and_then(|seg| seg.value());
+                found.is_some_and(|found| value_is(&found, value))
+            }
+        }
+    }
+}
+
+/// Segments printed by a printer. Segments outside `range` are left out, lines overlapping it
+/// are printed whole and of other segments only the rows overlapping it. Only the top-level
+/// headers and blocks matched by all of `select` are printed.
+#[derive(Clone, Default)]
+pub struct Filter {
+    /// Absolute byte range
+    pub range: Option<Range<usize>>,
+    pub select: Vec<Select>,
+}
+
+impl Filter {
+    pub(crate) fn overlaps(&self, start: usize, end: usize) -> bool {
+        match self.range {
+            Some(ref range) => start < range.end && range.start < end,
+            None => true,
+        }
+    }
+
+    /// Whether the row of a segment which isn't a line is printed
+    pub(crate) fn keeps_row(&self, seg: &Segment, start: usize, end: usize) -> bool {
+        match seg.kind {
+            SegmentKind::Line { .. } => true,
+            _ => self.overlaps(start, end),
+        }
+    }
+
+    /// Whether `seg` is matched by all `Value` selections
+    fn matches_values(&self, seg: SegmentRef) -> bool {
+        self.select.iter().filter(|select| matches!(select, Select::Value(..))).all(|select| select.matches(seg, None))
+    }
+
+    /// Top-level segments of `main` which are printed, with their bytes. `first_block` is the
+    /// index of the first block of `main` among all blocks counted by `count_blocks`, for
+    /// printing a few blocks at a time.
+    pub(crate) fn tops<'a>(&'a self, buf: &'a [u8], main: &'a Segment, first_block: usize) -> impl Iterator<Item = (&'a [u8], &'a Segment)> {
+        let mut index = first_block;
+        main.expand(buf).iter().filter_map(move |(&(begin, end), seg)| {
+            let buf = &buf[begin..end];
+            let seg_ref = SegmentRef::new(buf, seg);
+            if !self.matches_values(seg_ref) {
+                return None;
+            }
+            let block = match seg.kind {
+                SegmentKind::Block => {
+                    index += 1;
+                    Some(index - 1)
+                }
+                _ => None,
+            };
+            let selected = self.overlaps(seg.start, seg.start + buf.len()) && self.select.iter().all(|select| select.matches(seg_ref, block));
+            if selected { Some((buf, seg)) } else { None }
+        })
+    }
+
+    /// Number of top-level blocks of `main` which are indexed by `Select::Blocks`
+    pub(crate) fn count_blocks(&self, buf: &[u8], main: &Segment) -> usize {
+        let blocks = main.expand(buf).iter().filter(|&(_, seg)| matches!(seg.kind, SegmentKind::Block));
+        blocks.filter(|&(&(begin, end), seg)| self.matches_values(SegmentRef::new(&buf[begin..end], seg))).count()
+    }
+
+    /// Absolute ranges of the printed top-level segments, or all of `buf` when `main` has no
+    /// children. `range` isn't applied.
+    pub(crate) fn top_ranges(&self, buf: &[u8], main: &Segment) -> Vec<Range<usize>> {
+        if main.expand(buf).is_empty() {
+            return iter::once(0..buf.len()).collect();
+        }
+        self.tops(buf, main, 0).map(|(buf, seg)| seg.start..seg.start + buf.len()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tests::tree;
+
+    /// A header and blocks with ports 1, 6, 6, 1, 6 of one byte each
+    const BUF: [u8; 6] = [0, 1, 6, 6, 1, 6];
+
+    fn starts(filter: &Filter, first_block: usize) -> Vec<usize> {
+        let main = tree(&BUF, 1, 1);
+        filter.tops(&BUF, &main, first_block).map(|(_, seg)| seg.start).collect()
+    }
+
+    fn select(args: &[&str]) -> Filter {
+        Filter { range: None, select: args.iter().map(|arg| Select::from_arg(arg).unwrap()).collect() }
+    }
+
+    #[test]
+    fn from_arg() {
+        match Select::from_arg("120..130") {
+            Some(Select::Blocks(blocks)) => assert_eq!(blocks, 120..130),
+            _ => panic!("Not a block range"),
+        }
+        match Select::from_arg("block/dst port=443") {
+            Some(Select::Value(ref path, ref value)) => assert!(path == "block/dst port" && value == "443"),
+            _ => panic!("Not a value"),
+        }
+        match Select::from_arg("a=b..c") {
+            Some(Select::Value(ref path, ref value)) => assert!(path == "a" && value == "b..c"),
+            _ => panic!("Not a value"),
+        }
+        assert!(Select::from_arg("1..x").is_none());
+        assert!(Select::from_arg("nothing").is_none());
+    }
+
+    #[test]
+    fn tops() {
+        assert_eq!(starts(&Filter::default(), 0), vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(starts(&select(&["1..3"]), 0), vec![2, 3]);
+        assert_eq!(starts(&select(&["1..3"]), 2), vec![1]);
+        assert_eq!(starts(&select(&["port=6"]), 0), vec![2, 3, 5]);
+        // Blocks are counted among the blocks with the value
+        assert_eq!(starts(&select(&["port=6", "0..1"]), 0), vec![2]);
+        assert_eq!(starts(&select(&["2..3", "port=6"]), 0), vec![5]);
+        let range = Filter { range: Some(2..4), select: Vec::new() };
+        assert_eq!(starts(&range, 0), vec![2, 3]);
+    }
+
+    #[test]
+    fn count_blocks() {
+        let main = tree(&BUF, 1, 1);
+        assert_eq!(Filter::default().count_blocks(&BUF, &main), 5);
+        assert_eq!(select(&["port=6", "0..1"]).count_blocks(&BUF, &main), 3);
+    }
+}
@@ -0,0 +1,266 @@
+//! Export of the segment tree as JSON for other tools.
+//!
+//! The document looks like
+//!
+//! ```text
+//! {
+//!   "version": 1,
+//!   "len": 348,
+//!   "segments": [
+//!     {"kind": "block", "start": 28, "end": 68, "ty": "ascii", "segments": [
+//!       {"kind": "line", "start": 28, "end": 32, "ty": "le enum", "tag": "type",
+//!        "value": "iface descr (0x00000001)", "known": true, "paint": {"role": "header"}},
+//!       ...
+//!     ]},
+//!     ...
+//!   ]
+//! }
+//! ```
+//!
+//! `start` and `end` are absolute offsets, `end` is exclusive. `value` is `null` for types
+//! without a decoded value. `paint` is either `{"role": name}` or a style like
+//! `{"fg": "red", "bg": null, "bold": false, "underline": false, "dim": false}` where colors
+//! are basic color names, palette indices or `#rrggbb` strings. Lines split into bit fields
+//! have a `bits` array of `{"offset", "len", "tag", "value", "known"}` with bit offsets
+//! counted from the most significant bit of the line.
+//!
+//! `tag`, `value`, `known` and `paint` only exist on lines, `bits` and `segments` only when they
+//! aren't empty. New keys may be added without bumping `version`. Segments left out by the
+//! filter of the printer are missing, `len` is still the length of the whole file.
+
+use std::io::{self, Write};
+
+use diff::Change;
+use {expand_all, format_bits, format_value, is_unknown_value, ty_name};
+use {Color, Filter, Paint, Role, Segment, SegmentKind, SegmentRef, Style, StyleBuilder, Ty};
+
+pub struct JsonPrinter<B: AsRef<[u8]> = Vec<u8>> {
+    buf: B,
+    main: Segment,
+    filter: Filter,
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn color(color: Color) -> String {
+    match color {
+        Color::Black => escape("black"),
+        Color::Blue => escape("blue"),
+        Color::Cyan => escape("cyan"),
+        Color::Green => escape("green"),
+        Color::Magenta => escape("magenta"),
+        Color::Red => escape("red"),
+        Color::Yellow => escape("yellow"),
+        Color::White => escape("white"),
+        Color::Fixed(n) => n.to_string(),
+        Color::Rgb(r, g, b) => escape(&format!("#{:02x}{:02x}{:02x}", r, g, b)),
+    }
+}
+
+fn style(style: Style) -> String {
+    format!(
+        "{{\"fg\": {}, \"bg\": {}, \"bold\": {}, \"underline\": {}, \"dim\": {}}}",
+        color(style.fg),
+        style.bg.map(color).unwrap_or_else(|| "null".to_string()),
+        style.bold,
+        style.underline,
+        style.dim
+    )
+}
+
+fn paint(paint: Paint) -> String {
+    match paint {
+        Paint::Style(s) => style(s),
+        Paint::Role(role) => {
+            let name = match role {
+                Role::Header => "header",
+                Role::Length => "length",
+                Role::Checksum => "checksum",
+                Role::Payload => "payload",
+                Role::Error => "error",
+            };
+            format!("{{\"role\": {}}}", escape(name))
+        }
+    }
+}
+
+impl<B: AsRef<[u8]>> JsonPrinter<B> {
+    /// See `TermPrinter::new`
+    pub fn new(buf: B) -> Self {
+        JsonPrinter {
+            buf,
+            main: Segment::new(0, Ty::Ascii, SegmentKind::Main),
+            filter: Filter::default(),
+        }
+    }
+
+    /// Export only the segments selected by `filter`. Segments are exported whole.
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.filter = filter;
+    }
+
+    pub fn style_builder(&mut self) -> StyleBuilder<'_> {
+        StyleBuilder::new(self.buf.as_ref(), 0, &mut self.main.childs)
+    }
+
+    /// The styled segments, covering the whole buffer
+    pub fn root(&self) -> SegmentRef<'_> {
+        SegmentRef::new(self.buf.as_ref(), &self.main)
+    }
+
+    fn write_segment<W: Write>(&self, out: &mut W, buf: &[u8], s: &Segment, indent: usize) -> io::Result<()> {
+        let pad = "  ".repeat(indent);
+        let kind = match s.kind {
+            SegmentKind::Main => "main",
+            SegmentKind::Header => "header",
+            SegmentKind::Block => "block",
+            SegmentKind::Line { .. } => "line",
+        };
+        write!(out, "{}{{\"kind\": {}, \"start\": {}, \"end\": {}, \"ty\": {}", pad, escape(kind), s.start, s.start + buf.len(), escape(&ty_name(&s.ty)))?;
+        if let SegmentKind::Line { ref tag, paint: p } = s.kind {
+            let value = format_value(&s.ty, buf).map(|value| escape(&value)).unwrap_or_else(|| "null".to_string());
+            write!(out, ", \"tag\": {}, \"value\": {}, \"known\": {}, \"paint\": {}", escape(tag), value, !is_unknown_value(&s.ty, buf), paint(p))?;
+        }
+        if !s.bits.is_empty() {
+            writeln!(out, ", \"bits\": [")?;
+            for (i, field) in s.bits.iter().enumerate() {
+                let (_, value, known) = format_bits(buf, field);
+                write!(out, "{}  {{\"offset\": {}, \"len\": {}, \"tag\": {}, \"value\": {}, \"known\": {}}}", pad, field.offset, field.len, escape(&field.tag), escape(&value), known)?;
+                writeln!(out, "{}", if i + 1 < s.bits.len() { "," } else { "" })?;
+            }
+            write!(out, "{}]", pad)?;
+        }
+        let childs = s.expand(buf).iter().map(|(&(begin, end), child)| (&buf[begin..end], child));
+        let childs = childs.filter(|&(buf, child)| self.filter.overlaps(child.start, child.start + buf.len())).collect::<Vec<_>>();
+        if !childs.is_empty() {
+            writeln!(out, ", \"segments\": [")?;
+            self.write_segments(out, &childs, indent + 1)?;
+            write!(out, "{}]", pad)?;
+        }
+        write!(out, "}}")
+    }
+
+    fn write_segments<W: Write>(&self, out: &mut W, childs: &[(&[u8], &Segment)], indent: usize) -> io::Result<()> {
+        for (i, &(buf, child)) in childs.iter().enumerate() {
+            self.write_segment(out, buf, child, indent)?;
+            writeln!(out, "{}", if i + 1 < childs.len() { "," } else { "" })?;
+        }
+        Ok(())
+    }
+
+    /// Writes the whole document, see the module documentation
+    fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let buf = self.buf.as_ref();
+        writeln!(out, "{{\"version\": 1, \"len\": {}, \"segments\": [", buf.len())?;
+        let tops = self.filter.tops(buf, &self.main, 0).collect::<Vec<_>>();
+        expand_all(&tops);
+        self.write_segments(out, &tops, 1)?;
+        writeln!(out, "]}}")
+    }
+
+    pub fn print(self) {
+        let stdout = io::stdout();
+        self.write(&mut stdout.lock()).expect("Failed to write JSON");
+    }
+}
+
+/// Prints the changes between two trees as
+///
+/// ```text
+/// {"version": 1, "changes": [
+///   {"path": "block[1]/dst port", "old": {"start": 132, "end": 134, "value": "80"},
+///    "new": {"start": 132, "end": 134, "value": "443"}},
+///   ...
+/// ]}
+/// ```
+///
+/// `old` is `null` for added segments and `new` for removed ones.
+pub fn print_changes(changes: &[Change]) {
+    let side = |seg: Option<SegmentRef>| match seg {
+        Some(seg) => {
+            let value = seg.value().map(|value| escape(&value)).unwrap_or_else(|| "null".to_string());
+            format!("{{\"start\": {}, \"end\": {}, \"value\": {}}}", seg.start(), seg.end(), value)
+        }
+        None => "null".to_string(),
+    };
+    println!("{{\"version\": 1, \"changes\": [");
+    for (i, change) in changes.iter().enumerate() {
+        print!("  {{\"path\": {}, \"old\": {}, \"new\": {}}}", escape(&change.path), side(change.old), side(change.new));
+        println!("{}", if i + 1 < changes.len() { "," } else { "" });
+    }
+    println!("]}}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {BitTy, Endian};
+
+    #[test]
+    fn escapes() {
+        assert_eq!(escape("a\"b\\c\x01\n"), "\"a\\\"b\\\\c\\u0001\\n\"");
+        assert_eq!(escape("<é>"), "\"<é>\"");
+    }
+
+    #[test]
+    fn paints() {
+        assert_eq!(paint(Paint::Role(Role::Checksum)), "{\"role\": \"checksum\"}");
+        let style = Style { fg: Color::Rgb(1, 0xAB, 255), bg: Some(Color::Fixed(75)), bold: true, underline: false, dim: true };
+        assert_eq!(paint(Paint::Style(style)), "{\"fg\": \"#01abff\", \"bg\": 75, \"bold\": true, \"underline\": false, \"dim\": true}");
+        assert_eq!(color(Color::Magenta), "\"magenta\"");
+    }
+
+    #[test]
+    fn document() {
+        let buf = [0x45, 0x00, 0x12, 0x34, 0xFF];
+        let mut printer = JsonPrinter::new(&buf[..]);
+        {
+            // The last byte is left unstyled
+            let mut builder = printer.style_builder();
+            {
+                let mut block = builder.block(0, 4, Ty::Binary);
+                block.set_role(Role::Header);
+                {
+                    let mut bits = block.bitfield(1, Ty::Binary, "version + IHL");
+                    bits.field(4, BitTy::Num, "version");
+                    bits.field(4, BitTy::Num, "IHL");
+                }
+                block.set_color(Color::Red);
+                block.line(1, Ty::Enum(Endian::Big, &[(0, "zero")]), "kind \"x\"");
+                block.block(2, 4, Ty::Binary).line(2, Ty::BeNum, "port");
+            }
+        }
+        let mut out = Vec::new();
+        printer.write(&mut out).unwrap();
+        let expected = r#"{"version": 1, "len": 5, "segments": [
+  {"kind": "block", "start": 0, "end": 4, "ty": "binary", "segments": [
+    {"kind": "line", "start": 0, "end": 1, "ty": "binary", "tag": "version + IHL", "value": null, "known": true, "paint": {"role": "header"}, "bits": [
+      {"offset": 0, "len": 4, "tag": "version", "value": "4", "known": true},
+      {"offset": 4, "len": 4, "tag": "IHL", "value": "5", "known": true}
+    ]},
+    {"kind": "line", "start": 1, "end": 2, "ty": "be enum", "tag": "kind \"x\"", "value": "zero (0x00)", "known": true, "paint": {"fg": "red", "bg": null, "bold": false, "underline": false, "dim": false}},
+    {"kind": "block", "start": 2, "end": 4, "ty": "binary", "segments": [
+      {"kind": "line", "start": 2, "end": 4, "ty": "be num", "tag": "port", "value": "4660", "known": true, "paint": {"fg": "white", "bg": null, "bold": false, "underline": false, "dim": false}}
+    ]}
+  ]}
+]}
+"#;
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+    }
+}
@@ -0,0 +1,306 @@
+//! Arrangement of the bytes of a segment into rows, shared by all printers.
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+
+use Endian;
+
+/// Representation of the cells in the hex column
+#[derive(Copy, Clone, PartialEq)]
+pub enum CellFormat {
+    Hex,
+    Binary,
+    Octal,
+    Unsigned,
+    Signed,
+    /// Hex words of 2, 4 or 8 bytes
+    Word(usize, Endian),
+}
+
+impl CellFormat {
+    pub fn from_name(name: &str) -> Option<CellFormat> {
+        match name {
+            "hex" => Some(CellFormat::Hex),
+            "bin" => Some(CellFormat::Binary),
+            "oct" => Some(CellFormat::Octal),
+            "dec" => Some(CellFormat::Unsigned),
+            "signed" => Some(CellFormat::Signed),
+            "u16le" => Some(CellFormat::Word(2, Endian::Little)),
+            "u16be" => Some(CellFormat::Word(2, Endian::Big)),
+            "u32le" => Some(CellFormat::Word(4, Endian::Little)),
+            "u32be" => Some(CellFormat::Word(4, Endian::Big)),
+            "u64le" => Some(CellFormat::Word(8, Endian::Little)),
+            "u64be" => Some(CellFormat::Word(8, Endian::Big)),
+            _ => None,
+        }
+    }
+
+    /// Number of bytes shown in a single cell
+    pub fn bytes(self) -> usize {
+        match self {
+            CellFormat::Word(size, _) => size,
+            _ => 1,
+        }
+    }
+
+    /// Number of chars of a single cell
+    pub fn width(self) -> usize {
+        match self {
+            CellFormat::Hex => 2,
+            CellFormat::Binary => 8,
+            CellFormat::Octal | CellFormat::Unsigned => 3,
+            CellFormat::Signed => 4,
+            CellFormat::Word(size, _) => size * 2,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub enum Radix {
+    Hex,
+    Decimal,
+}
+
+/// Format of the offset column
+#[derive(Clone)]
+pub struct Offsets {
+    pub radix: Radix,
+    /// Minimal number of digits
+    pub width: usize,
+    /// Added to every offset, for example the load address of a memory dump
+    pub base: u64,
+}
+
+impl Default for Offsets {
+    fn default() -> Offsets {
+        Offsets {
+            radix: Radix::Hex,
+            width: 8,
+            base: 0,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Layout {
+    pub bytes_per_row: usize,
+    /// Number of bytes in a group. Zero disables grouping.
+    pub group_size: usize,
+    /// Printed in front of every group of bytes
+    pub group_separator: String,
+    pub uppercase: bool,
+    /// Format of cells in segments which don't set their own format
+    pub cell: CellFormat,
+    /// Prefix every row with its offset
+    pub offsets: Option<Offsets>,
+    /// Start rows at multiples of `bytes_per_row` instead of at the start of every segment
+    pub aligned: bool,
+}
+
+impl Default for Layout {
+    fn default() -> Layout {
+        Layout {
+            bytes_per_row: 32,
+            group_size: 8,
+            group_separator: " ".to_string(),
+            uppercase: true,
+            cell: CellFormat::Hex,
+            offsets: Some(Offsets::default()),
+            aligned: false,
+        }
+    }
+}
+
+impl Layout {
+    fn group_start(&self, i: usize) -> bool {
+        self.group_size != 0 && i.is_multiple_of(self.group_size)
+    }
+
+    /// Printed in front of the `i`th byte of a hex row
+    pub fn hex_separator(&self, i: usize) -> &str {
+        if self.group_start(i) { &self.group_separator } else { "" }
+    }
+
+    /// Printed in front of the `i`th cell of a text row
+    pub fn text_separator(&self, i: usize) -> &str {
+        if i != 0 && self.group_start(i) { &self.group_separator } else { "" }
+    }
+
+    /// `bytes_per_row`, which must not be zero
+    pub(crate) fn row_len(&self) -> usize {
+        assert!(self.bytes_per_row != 0, "Rows must hold at least one byte");
+        self.bytes_per_row
+    }
+
+    fn hex_bytes(&self, bytes: &[u8]) -> String {
+        bytes.iter().map(|b| if self.uppercase { format!("{:02X}", b) } else { format!("{:02x}", b) }).collect()
+    }
+
+    /// A single cell. Words which are cut off are shown as hex bytes in memory order.
+    pub fn cell(&self, format: CellFormat, bytes: &[u8]) -> String {
+        match format {
+            CellFormat::Hex => self.hex_bytes(bytes),
+            CellFormat::Binary => format!("{:08b}", bytes[0]),
+            CellFormat::Octal => format!("{:03o}", bytes[0]),
+            CellFormat::Unsigned => format!("{:3}", bytes[0]),
+            CellFormat::Signed => format!("{:4}", bytes[0] as i8),
+            CellFormat::Word(size, _) if bytes.len() < size => {
+                format!("{:.<1$}", self.hex_bytes(bytes), size * 2)
+            }
+            CellFormat::Word(size, endian) => {
+                let word = match (size, endian) {
+                    (2, Endian::Little) => LittleEndian::read_u16(bytes) as u64,
+                    (2, Endian::Big) => BigEndian::read_u16(bytes) as u64,
+                    (4, Endian::Little) => LittleEndian::read_u32(bytes) as u64,
+                    (4, Endian::Big) => BigEndian::read_u32(bytes) as u64,
+                    (8, Endian::Little) => LittleEndian::read_u64(bytes),
+                    (8, Endian::Big) => BigEndian::read_u64(bytes),
+                    (size, _) => panic!("Invalid word size {}", size),
+                };
+                if self.uppercase {
+                    format!("{:01$X}", word, size * 2)
+                } else {
+                    format!("{:01$x}", word, size * 2)
+                }
+            }
+        }
+    }
+
+    /// Offset column of a row starting at the absolute offset `offset`
+    pub fn offset_column(&self, offset: usize) -> String {
+        let offsets = match self.offsets {
+            Some(ref offsets) => offsets,
+            None => return String::new(),
+        };
+        let offset = offsets.base.wrapping_add(offset as u64);
+        match (offsets.radix, self.uppercase) {
+            (Radix::Hex, true) => format!("{:01$X}:", offset, offsets.width),
+            (Radix::Hex, false) => format!("{:01$x}:", offset, offsets.width),
+            (Radix::Decimal, _) => format!("{:01$}:", offset, offsets.width),
+        }
+    }
+
+    /// Spaces as wide as the offset column, for rows which don't start at an offset
+    pub fn offset_padding(&self) -> String {
+        match self.offsets {
+            Some(_) => " ".repeat(self.offset_column(0).chars().count()),
+            None => String::new(),
+        }
+    }
+
+    /// Hex column of a row. Cells missing at the end of the row are shown as dots.
+    pub fn hex_row(&self, format: CellFormat, chunk: &[u8]) -> String {
+        assert!(chunk.len() <= self.bytes_per_row, "Chunk len {} exceeds row len {}", chunk.len(), self.bytes_per_row);
+        let mut row = String::with_capacity(self.bytes_per_row * (format.width() + 2));
+        for i in (0..self.bytes_per_row).step_by(format.bytes()) {
+            row.push_str(self.hex_separator(i));
+            match chunk.get(i..) {
+                Some(bytes) if !bytes.is_empty() => {
+                    row.push_str(&self.cell(format, &bytes[..bytes.len().min(format.bytes())]))
+                }
+                _ => row.push_str(&".".repeat(format.width())),
+            }
+            row.push(' ');
+        }
+        row
+    }
+
+    /// Width in chars of every hex row
+    pub fn hex_row_width(&self, format: CellFormat) -> usize {
+        self.hex_row(format, &[]).chars().count()
+    }
+
+    /// Text column of a row. Cells missing at the end of the row are shown as `.`.
+    pub fn text_row(&self, cells: &[char]) -> String {
+        let mut text = String::with_capacity(self.bytes_per_row * 2);
+        for i in 0..self.bytes_per_row {
+            text.push_str(self.text_separator(i));
+            text.push(cells.get(i).cloned().unwrap_or('.'));
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Rows of 7 bytes in groups of 4
+    fn layout() -> Layout {
+        Layout { bytes_per_row: 7, group_size: 4, ..Layout::default() }
+    }
+
+    #[test]
+    fn cells() {
+        let layout = Layout::default();
+        assert_eq!(layout.cell(CellFormat::Hex, &[0xAB]), "AB");
+        assert_eq!(Layout { uppercase: false, ..Layout::default() }.cell(CellFormat::Hex, &[0xAB]), "ab");
+        assert_eq!(layout.cell(CellFormat::Binary, &[5]), "00000101");
+        assert_eq!(layout.cell(CellFormat::Octal, &[8]), "010");
+        assert_eq!(layout.cell(CellFormat::Unsigned, &[7]), "  7");
+        assert_eq!(layout.cell(CellFormat::Signed, &[0xFF]), "  -1");
+        assert_eq!(layout.cell(CellFormat::Signed, &[0x80]), "-128");
+        assert_eq!(layout.cell(CellFormat::Signed, &[0x7F]), " 127");
+    }
+
+    #[test]
+    fn words() {
+        let layout = Layout::default();
+        assert_eq!(layout.cell(CellFormat::Word(2, Endian::Little), &[0x01, 0xAB]), "AB01");
+        assert_eq!(layout.cell(CellFormat::Word(2, Endian::Big), &[0x01, 0xAB]), "01AB");
+        assert_eq!(layout.cell(CellFormat::Word(4, Endian::Little), &[1, 0, 0, 0]), "00000001");
+        assert_eq!(layout.cell(CellFormat::Word(8, Endian::Big), &[0, 0, 0, 0, 0, 0, 1, 0]), "0000000000000100");
+        // Cut off words keep the memory order
+        assert_eq!(layout.cell(CellFormat::Word(4, Endian::Little), &[1, 0]), "0100....");
+        assert_eq!(layout.cell(CellFormat::Word(2, Endian::Big), &[7]), "07..");
+    }
+
+    #[test]
+    fn hex_rows() {
+        let layout = layout();
+        assert_eq!(layout.hex_row(CellFormat::Hex, &[1, 2, 3, 4, 5, 6, 7]), " 01 02 03 04  05 06 07 ");
+        assert_eq!(layout.hex_row(CellFormat::Hex, &[1, 2, 3, 4, 5]), " 01 02 03 04  05 .. .. ");
+        assert_eq!(layout.hex_row(CellFormat::Word(2, Endian::Big), &[1, 2, 3, 4, 5, 6, 7]), " 0102 0304  0506 07.. ");
+        assert_eq!(layout.hex_row(CellFormat::Signed, &[0xFF]), "   -1 .... .... ....  .... .... .... ");
+        assert_eq!(layout.hex_row_width(CellFormat::Hex), 23);
+
+        let ungrouped = Layout { group_size: 0, ..layout };
+        assert_eq!(ungrouped.hex_row(CellFormat::Hex, &[1, 2, 3]), "01 02 03 .. .. .. .. ");
+        assert_eq!(ungrouped.text_row(&['a', 'b']), "ab.....");
+    }
+
+    #[test]
+    #[should_panic(expected = "Chunk len 8 exceeds row len 7")]
+    fn long_chunk() {
+        layout().hex_row(CellFormat::Hex, &[0; 8]);
+    }
+
+    #[test]
+    fn text_rows() {
+        assert_eq!(layout().text_row(&['a', 'b', 'c', 'd', 'e']), "abcd e..");
+    }
+
+    #[test]
+    fn offsets() {
+        let layout = Layout::default();
+        assert_eq!(layout.offset_column(0x1F), "0000001F:");
+        assert_eq!(layout.offset_padding(), "         ");
+
+        let offsets = Offsets { radix: Radix::Hex, width: 4, base: 0x1000 };
+        let based = Layout { uppercase: false, offsets: Some(offsets), ..Layout::default() };
+        assert_eq!(based.offset_column(0xAB), "10ab:");
+        assert_eq!(based.offset_column(0x12345), "13345:");
+
+        let offsets = Offsets { radix: Radix::Decimal, width: 6, base: 0 };
+        let decimal = Layout { offsets: Some(offsets), ..Layout::default() };
+        assert_eq!(decimal.offset_column(123), "000123:");
+        assert_eq!(decimal.offset_padding().len(), 7);
+
+        // The base wraps around instead of overflowing
+        let offsets = Offsets { radix: Radix::Hex, width: 1, base: u64::MAX };
+        assert_eq!(Layout { offsets: Some(offsets), ..Layout::default() }.offset_column(2), "1:");
+
+        let none = Layout { offsets: None, ..Layout::default() };
+        assert_eq!(none.offset_column(0x1F), "");
+        assert_eq!(none.offset_padding(), "");
+    }
+}
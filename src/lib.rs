@@ -1,17 +1,128 @@
-extern crate itertools;
 extern crate byteorder;
 extern crate termion;
+extern crate regex;
 
-use std::collections::HashMap;
-use itertools::Itertools;
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::{Arc, OnceLock};
+use std::thread;
 use byteorder::{BigEndian, LittleEndian, ByteOrder};
-use termion::color::*;
+use termion::{color, style};
+
+mod aligned;
+pub mod diff;
+pub mod filter;
+pub mod json;
+pub mod layout;
+pub mod query;
+pub mod search;
+pub mod stats;
+pub mod strings;
+mod stream;
+pub mod text;
+pub mod theme;
+pub mod varint;
+
+pub use filter::{Filter, Select};
+pub use diff::DiffPrinter;
+pub use json::JsonPrinter;
+pub use layout::{CellFormat, Layout, Offsets, Radix};
+pub use query::SegmentRef;
+pub use search::{Match, Pattern};
+pub use stats::Stats;
+pub use stream::{HtmlStream, TermStream};
+pub use text::{CharSet, Encoding};
+pub use theme::{Color, ColorSupport, Paint, Role, Style, Theme};
 
 #[derive(Clone)]
 struct Segment {
+    /// Absolute offset of the first byte
+    start: usize,
     ty: Ty,
     kind: SegmentKind,
-    childs: HashMap<(usize, usize), Segment>,
+    childs: Childs,
+    bits: Vec<BitField>,
+    /// Overrides the cell format of the layout
+    cell: Option<CellFormat>,
+    /// Children styled on first use instead of `childs`
+    lazy: Option<Lazy>,
+}
+
+type BlockStyler = Arc<dyn Fn(StyleBuilder) + Send + Sync>;
+
+#[derive(Clone)]
+struct Lazy {
+    styler: BlockStyler,
+    childs: OnceLock<Childs>,
+}
+
+impl Segment {
+    fn new(start: usize, ty: Ty, kind: SegmentKind) -> Segment {
+        Segment {
+            start,
+            ty,
+            kind,
+            childs: Childs::new(),
+            bits: Vec::new(),
+            cell: None,
+            lazy: None,
+        }
+    }
+
+    /// Children of the segment. The styler of a lazy block runs on the first call.
+    fn expand(&self, buf: &[u8]) -> &Childs {
+        let lazy = match self.lazy {
+            Some(ref lazy) => lazy,
+            None => return &self.childs,
+        };
+        lazy.childs.get_or_init(|| {
+            let mut childs = Childs::new();
+            (lazy.styler)(StyleBuilder::new(buf, self.start, &mut childs));
+            childs
+        })
+    }
+}
+
+/// Children of a segment by their range relative to the parent, ordered by offset
+type Childs = BTreeMap<(usize, usize), Segment>;
+
+/// Maps `items` on up to `available_parallelism` threads, keeping their order. Items which fit
+/// in a single chunk are mapped on the calling thread.
+fn map_parallel<T: Sync, R: Send, F: Fn(&T) -> R + Sync>(items: &[T], f: F) -> Vec<R> {
+    let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    map_chunks(items, items.len().div_ceil(threads).max(1), f)
+}
+
+/// Maps every chunk of `chunk_len` items on its own thread
+fn map_chunks<T: Sync, R: Send, F: Fn(&T) -> R + Sync>(items: &[T], chunk_len: usize, f: F) -> Vec<R> {
+    if items.len() <= chunk_len {
+        return items.iter().map(f).collect();
+    }
+    let f = &f;
+    thread::scope(|scope| {
+        let handles = items.chunks(chunk_len).map(|chunk| scope.spawn(move || chunk.iter().map(f).collect::<Vec<_>>())).collect::<Vec<_>>();
+        handles.into_iter().flat_map(|handle| handle.join().expect("Styler panicked")).collect()
+    })
+}
+
+/// Runs the stylers of the lazy blocks among `segs` on multiple threads before they are printed
+fn expand_all(segs: &[(&[u8], &Segment)]) {
+    let lazy = segs.iter().filter(|&&(_, seg)| seg.lazy.as_ref().is_some_and(|lazy| lazy.childs.get().is_none())).collect::<Vec<_>>();
+    map_parallel(&lazy, |&&(buf, seg)| {
+        seg.expand(buf);
+    });
+}
+
+/// Inserts a child, panicking when it overlaps one of its siblings
+fn insert_child(childs: &mut Childs, begin: usize, end: usize, seg: Segment) -> &mut Segment {
+    assert!(!childs.contains_key(&(begin, end)), "Segment {}..{} was already added", begin, end);
+    if let Some((&(prev_begin, prev_end), _)) = childs.range(..(begin, end)).next_back() {
+        assert!(prev_end <= begin, "Segment {}..{} overlaps {}..{}", begin, end, prev_begin, prev_end);
+    }
+    if let Some((&(next_begin, next_end), _)) = childs.range((begin, end)..).next() {
+        assert!(end <= next_begin, "Segment {}..{} overlaps {}..{}", begin, end, next_begin, next_end);
+    }
+    childs.entry((begin, end)).or_insert(seg)
 }
 
 #[derive(Clone)]
@@ -19,7 +130,7 @@ pub enum SegmentKind {
     Main,
     Header,
     Block,
-    Line { tag: String, color: Color },
+    Line { tag: String, paint: Paint },
 }
 
 #[derive(Clone)]
@@ -29,6 +140,21 @@ pub enum Ty {
     BeNum,
     LeNum,
     Ip4,
+    /// Unsigned LEB128, also used for protobuf varints
+    Uleb128,
+    /// Signed LEB128
+    Sleb128,
+    /// Zigzag encoded protobuf varint
+    ZigZag,
+    /// Big endian base 128 as used by MIDI
+    Vlq,
+    /// Text in the given encoding
+    Text(Encoding),
+    /// Number which is shown by name, like an ethertype
+    Enum(Endian, NameTable),
+    /// Set of named bits, like tcp flags
+    Flags(Endian, NameTable),
+    /// Text given by the styler. Printers show only the text, not the bytes as a number.
     Custom(String),
 }
 
@@ -38,104 +164,346 @@ impl Ty {
     }
 }
 
+#[derive(Copy, Clone, PartialEq)]
+pub enum Endian {
+    Big,
+    Little,
+}
+
+/// Value to name mapping used to give numbers a meaning. For flags the values are bit masks.
+pub type NameTable = &'static [(u64, &'static str)];
+
+/// Formats a value like `IPv6 (0x86DD)`. Returns false when the value is not in the table.
+fn format_enum(names: NameTable, value: u64, digits: usize) -> (String, bool) {
+    match names.iter().find(|&&(n, _)| n == value) {
+        Some(&(_, name)) => (format!("{} (0x{:02$X})", name, value, digits), true),
+        None => (format!("<unknown> (0x{:01$X})", value, digits), false),
+    }
+}
+
+/// Formats a value like `SYN|ACK (0x012)`, listing the set flags from the lowest mask up.
+/// Returns false when bits not in the table are set.
+fn format_flags(names: NameTable, value: u64, digits: usize) -> (String, bool) {
+    let mut set = names.iter().filter(|&&(mask, _)| mask != 0 && value & mask == mask).collect::<Vec<_>>();
+    set.sort_by_key(|&&(mask, _)| mask);
+    let mut set = set.into_iter().map(|&(_, name)| name.to_string()).collect::<Vec<_>>();
+    let unknown = names.iter().fold(value, |rest, &(mask, _)| rest & !mask);
+    if unknown != 0 {
+        set.push(format!("0x{:X}", unknown));
+    }
+    let text = if set.is_empty() { "none".to_string() } else { set.join("|") };
+    (format!("{} (0x{:02$X})", text, value, digits), unknown == 0)
+}
+
+/// Formats `Ty::Enum` and `Ty::Flags` values
+fn format_named(ty: &Ty, buf: &[u8]) -> Option<(String, bool)> {
+    match *ty {
+        Ty::Enum(endian, names) => Some(format_enum(names, read_endian(endian, buf), buf.len() * 2)),
+        Ty::Flags(endian, names) => Some(format_flags(names, read_endian(endian, buf), buf.len() * 2)),
+        _ => None,
+    }
+}
+
+/// Decoded value of a segment, if its type has one
+fn format_value(ty: &Ty, buf: &[u8]) -> Option<String> {
+    match *ty {
+        Ty::Ascii | Ty::Binary => None,
+        Ty::Text(encoding) => Some(encoding.decode(buf)),
+        Ty::BeNum => Some(read_num::<BigEndian>(buf).to_string()),
+        Ty::LeNum => Some(read_num::<LittleEndian>(buf).to_string()),
+        Ty::Ip4 => {
+            assert!(buf.len() == 4, "Wrong len for ipv4 addr");
+            Some(format!("{}.{}.{}.{}", buf[0], buf[1], buf[2], buf[3]))
+        }
+        Ty::Uleb128 | Ty::Sleb128 | Ty::ZigZag | Ty::Vlq => Some(format_varint(ty, buf)),
+        Ty::Enum(..) | Ty::Flags(..) => format_named(ty, buf).map(|(value, _)| value),
+        Ty::Custom(ref custom) => Some(custom.clone()),
+    }
+}
+
+/// Unknown enum values and flags are highlighted
+fn is_unknown_value(ty: &Ty, buf: &[u8]) -> bool {
+    format_named(ty, buf).is_some_and(|(_, known)| !known)
+}
+
+fn ty_name(ty: &Ty) -> String {
+    match *ty {
+        Ty::Ascii => "ascii".to_string(),
+        Ty::Binary => "binary".to_string(),
+        Ty::BeNum => "be num".to_string(),
+        Ty::LeNum => "le num".to_string(),
+        Ty::Ip4 => "ipv4".to_string(),
+        Ty::Uleb128 => "uleb128".to_string(),
+        Ty::Sleb128 => "sleb128".to_string(),
+        Ty::ZigZag => "zigzag".to_string(),
+        Ty::Vlq => "vlq".to_string(),
+        Ty::Text(encoding) => format!("text {}", encoding.name()),
+        Ty::Enum(Endian::Big, _) => "be enum".to_string(),
+        Ty::Enum(Endian::Little, _) => "le enum".to_string(),
+        Ty::Flags(Endian::Big, _) => "be flags".to_string(),
+        Ty::Flags(Endian::Little, _) => "le flags".to_string(),
+        Ty::Custom(_) => "custom".to_string(),
+    }
+}
+
+/// Text column cells of all bytes of a segment, decoded at once so that rows can start inside
+/// a character. Only text segments have a text column.
+fn text_cells(ty: &Ty, charset: CharSet, buf: &[u8]) -> Option<Vec<char>> {
+    match *ty {
+        Ty::Ascii => Some(charset.cells(buf, buf.len())),
+        Ty::Text(encoding) => Some(encoding.cells(buf, buf.len())),
+        _ => None,
+    }
+}
+
+#[derive(Clone)]
+pub enum BitTy {
+    Num,
+    Enum(NameTable),
+    Flags(NameTable),
+}
+
+#[derive(Clone)]
+struct BitField {
+    /// Offset in bits from the most significant bit of the first byte
+    offset: usize,
+    len: usize,
+    ty: BitTy,
+    tag: String,
+}
+
+fn read_bits(buf: &[u8], offset: usize, len: usize) -> u64 {
+    (offset..offset + len).fold(0, |value, bit| (value << 1) | ((buf[bit / 8] >> (7 - bit % 8)) & 1) as u64)
+}
+
+/// Returns the bit diagram and the formatted value of a bit field, and whether the value is known
+fn format_bits(buf: &[u8], field: &BitField) -> (String, String, bool) {
+    let mut diagram = String::with_capacity(buf.len() * 9);
+    for bit in 0..buf.len() * 8 {
+        if bit != 0 && bit % 8 == 0 {
+            diagram.push(' ');
+        }
+        if bit < field.offset || bit >= field.offset + field.len {
+            diagram.push('.');
+        } else if read_bits(buf, bit, 1) == 1 {
+            diagram.push('1');
+        } else {
+            diagram.push('0');
+        }
+    }
+    let value = read_bits(buf, field.offset, field.len);
+    let digits = field.len.div_ceil(4);
+    let (value, known) = match field.ty {
+        BitTy::Num => (value.to_string(), true),
+        BitTy::Enum(names) => format_enum(names, value, digits),
+        BitTy::Flags(names) => format_flags(names, value, digits),
+    };
+    (diagram, value, known)
+}
+
+/// How the length of a string is determined
 #[derive(Copy, Clone)]
-pub enum Color {
-    Blue,
-    Cyan,
-    Green,
-    Magenta,
-    Red,
-    Yellow,
-    White,
+pub enum StrLen {
+    Fixed(usize),
+    /// The NUL terminator is part of the string segment
+    NulTerminated,
+    /// Prefixed by a number of the given size containing the length in bytes
+    Prefixed(usize, Endian),
 }
 
 pub struct StyleBuilder<'a> {
     pub buf: &'a [u8],
-    childs: &'a mut HashMap<(usize, usize), Segment>,
-    part_color: Color,
+    offset: usize,
+    childs: &'a mut Childs,
+    part_paint: Paint,
+    cell_format: Option<CellFormat>,
     index: usize,
 }
 
 impl<'a> StyleBuilder<'a> {
+    /// Builder for the children of the segment at `offset` holding `buf`
+    pub(crate) fn new(buf: &'a [u8], offset: usize, childs: &'a mut Childs) -> StyleBuilder<'a> {
+        StyleBuilder { buf, offset, childs, part_paint: Paint::Style(Style::fg(Color::White)), cell_format: None, index: 0 }
+    }
+
     pub fn set_color(&mut self, color: Color){
-        self.part_color = color;
-    }
-    pub fn header(&mut self, begin: usize, end: usize, ty: Ty) -> StyleBuilder {
-        self.childs.insert(
-            (begin, end),
-            Segment {
-                ty: ty,
-                kind: SegmentKind::Header,
-                childs: HashMap::new(),
-            },
-        );
-        let seg = self.childs.get_mut(&(begin, end)).unwrap();
-        StyleBuilder {
-            buf: &self.buf[begin..end],
-            childs: &mut seg.childs,
-            part_color: Color::White,
-            index: 0,
+        self.part_paint = Paint::Style(Style::fg(color));
+    }
+
+    pub fn set_style(&mut self, style: Style) {
+        self.part_paint = Paint::Style(style);
+    }
+
+    /// Style the following lines by the theme of the printer
+    pub fn set_role(&mut self, role: Role) {
+        self.part_paint = Paint::Role(role);
+    }
+
+    /// Show the cells of the following lines in `format` instead of the format of the layout.
+    /// `None` goes back to the layout. Rows are padded to the width of their own format, so a
+    /// format wider than the one of the layout shifts the columns after the hex column.
+    pub fn set_cell_format(&mut self, format: Option<CellFormat>) {
+        self.cell_format = format;
+    }
+
+    /// Lines of zero bytes are left out, they hold no value and would collide with each other
+    fn insert_line(&mut self, begin: usize, end: usize, ty: Ty, tag: String) {
+        if begin == end {
+            return;
         }
+        let mut seg = Segment::new(self.offset + begin, ty, SegmentKind::Line { tag, paint: self.part_paint });
+        seg.cell = self.cell_format;
+        insert_child(self.childs, begin, end, seg);
     }
 
-    pub fn block(&mut self, begin: usize, end: usize, ty: Ty) -> StyleBuilder {
-        self.childs.insert(
-            (begin, end),
-            Segment {
-                ty: ty,
-                kind: SegmentKind::Block,
-                childs: HashMap::new(),
-            },
-        );
-        let seg = self.childs.get_mut(&(begin, end)).unwrap();
-        StyleBuilder {
-            buf: &self.buf[begin..end],
-            childs: &mut seg.childs,
-            part_color: Color::White,
-            index: 0,
+    pub fn header(&mut self, begin: usize, end: usize, ty: Ty) -> StyleBuilder<'_> {
+        let seg = insert_child(self.childs, begin, end, Segment::new(self.offset + begin, ty, SegmentKind::Header));
+        StyleBuilder::new(&self.buf[begin..end], self.offset + begin, &mut seg.childs)
+    }
+
+    pub fn block(&mut self, begin: usize, end: usize, ty: Ty) -> StyleBuilder<'_> {
+        let seg = insert_child(self.childs, begin, end, Segment::new(self.offset + begin, ty, SegmentKind::Block));
+        StyleBuilder::new(&self.buf[begin..end], self.offset + begin, &mut seg.childs)
+    }
+
+    /// Adds a block for every range and styles them on multiple threads. The blocks must not
+    /// depend on each other.
+    pub fn blocks<F>(&mut self, ranges: &[(usize, usize)], ty: Ty, styler: F)
+    where
+        F: Fn(StyleBuilder) + Sync,
+    {
+        let (buf, offset) = (self.buf, self.offset);
+        let blocks = map_parallel(ranges, |&(begin, end)| {
+            let mut seg = Segment::new(offset + begin, ty.clone(), SegmentKind::Block);
+            styler(StyleBuilder::new(&buf[begin..end], offset + begin, &mut seg.childs));
+            seg
+        });
+        for (&(begin, end), seg) in ranges.iter().zip(blocks) {
+            insert_child(self.childs, begin, end, seg);
         }
     }
 
+    /// Adds a block which is only styled by `styler` when its children are needed, for example
+    /// when it is printed. The HTML printer shows it collapsed unless it contains a mark.
+    pub fn lazy_block<F>(&mut self, begin: usize, end: usize, ty: Ty, styler: F)
+    where
+        F: Fn(StyleBuilder) + Send + Sync + 'static,
+    {
+        let mut seg = Segment::new(self.offset + begin, ty, SegmentKind::Block);
+        seg.lazy = Some(Lazy { styler: Arc::new(styler), childs: OnceLock::new() });
+        insert_child(self.childs, begin, end, seg);
+    }
+
     pub fn index(&self) -> usize { self.index }
 
+    /// Absolute offset of `buf` in the viewed file
+    pub fn offset(&self) -> usize { self.offset }
+
+    /// Adds a line of `len` bytes at the index. Empty lines are left out.
     pub fn line<S: Into<String>>(&mut self, len: usize, ty: Ty, tag: S) {
         assert!(self.index + len <= self.buf.len(), "Too big len {} exceeds buf len {} (index {})", len, self.buf.len(), self.index);
-        self.childs.insert(
-            (self.index, self.index + len),
-            Segment {
-                ty: ty,
-                kind: SegmentKind::Line { tag: tag.into(), color: self.part_color },
-                childs: HashMap::new(),
-            },
-        );
+        let index = self.index;
+        self.insert_line(index, index + len, ty, tag.into());
         self.index += len;
     }
 
     pub fn line_until<S: Into<String>>(&mut self, end: usize, ty: Ty, tag: S) {
         assert!(self.index <= end, "Index {} bigger than end {}", self.index, end);
-        self.childs.insert(
-            (self.index, end),
-            Segment {
-                ty: ty,
-                kind: SegmentKind::Line { tag: tag.into(), color: self.part_color },
-                childs: HashMap::new(),
-            },
-        );
+        let index = self.index;
+        self.insert_line(index, end, ty, tag.into());
         self.index = end;
     }
+
+    /// Adds a line which is further split into bit fields by the returned `BitBuilder`. Unlike
+    /// other lines it can't be empty, as its fields would have no line to belong to.
+    pub fn bitfield<S: Into<String>>(&mut self, len: usize, ty: Ty, tag: S) -> BitBuilder<'_> {
+        assert!(len != 0, "Bit field line at index {} is empty", self.index);
+        let begin = self.index;
+        self.line(len, ty, tag);
+        let seg = self.childs.get_mut(&(begin, begin + len)).unwrap();
+        BitBuilder {
+            buf: &self.buf[begin..begin + len],
+            bits: &mut seg.bits,
+            index: 0,
+        }
+    }
+
+    /// Adds a string line and returns the decoded text. A length prefix gets its own line.
+    pub fn string<S: Into<String>>(&mut self, len: StrLen, encoding: Encoding, tag: S) -> String {
+        let tag = tag.into();
+        let len = match len {
+            StrLen::Fixed(len) => len,
+            StrLen::NulTerminated => {
+                let unit = encoding.unit_len();
+                self.buf[self.index..]
+                    .chunks(unit)
+                    .position(|c| c.len() == unit && c.iter().all(|&b| b == 0))
+                    .map(|units| (units + 1) * unit)
+                    .unwrap_or_else(|| panic!("Missing NUL terminator for string at index {}", self.index))
+            }
+            StrLen::Prefixed(size, endian) => {
+                let len = read_endian(endian, &self.buf[self.index..self.index + size]) as usize;
+                let ty = match endian {
+                    Endian::Big => Ty::BeNum,
+                    Endian::Little => Ty::LeNum,
+                };
+                self.line(size, ty, format!("{} len", tag));
+                len
+            }
+        };
+        assert!(self.index + len <= self.buf.len(), "Too big len {} exceeds buf len {} (index {})", len, self.buf.len(), self.index);
+        let text = encoding.decode(&self.buf[self.index..self.index + len]);
+        self.line(len, Ty::Text(encoding), tag);
+        text
+    }
+
+    pub fn uleb128<S: Into<String>>(&mut self, tag: S) -> u64 {
+        let (value, len) = varint::read_uleb128(&self.buf[self.index..])
+            .unwrap_or_else(|| panic!("Truncated uleb128 at index {}", self.index));
+        self.line(len, Ty::Uleb128, tag);
+        value
+    }
+
+    pub fn sleb128<S: Into<String>>(&mut self, tag: S) -> i64 {
+        let (value, len) = varint::read_sleb128(&self.buf[self.index..])
+            .unwrap_or_else(|| panic!("Truncated sleb128 at index {}", self.index));
+        self.line(len, Ty::Sleb128, tag);
+        value
+    }
+
+    pub fn zigzag<S: Into<String>>(&mut self, tag: S) -> i64 {
+        let (value, len) = varint::read_zigzag(&self.buf[self.index..])
+            .unwrap_or_else(|| panic!("Truncated zigzag varint at index {}", self.index));
+        self.line(len, Ty::ZigZag, tag);
+        value
+    }
+
+    pub fn vlq<S: Into<String>>(&mut self, tag: S) -> u64 {
+        let (value, len) = varint::read_vlq(&self.buf[self.index..])
+            .unwrap_or_else(|| panic!("Truncated vlq at index {}", self.index));
+        self.line(len, Ty::Vlq, tag);
+        value
+    }
+}
+
+/// Splits a line into fields of arbitrary bit width. Bits are numbered starting at the most
+/// significant bit of the first byte, like in network protocol diagrams.
+pub struct BitBuilder<'a> {
+    pub buf: &'a [u8],
+    bits: &'a mut Vec<BitField>,
+    index: usize,
 }
 
-fn make_ascii(c: char) -> char {
-    match c {
-        // _ if c.is_ascii_alphanumeric() || c.is_ascii_punctuation() => c,
-        'a'...'z' | 'A'...'Z' | '0'...'9' | ':' | ';' | '@' | '/' | '\\' | '|' | '?' | '!' |
-        '+' | '*' | '.' | ',' | ' ' | '-' | '_' | '\'' | '"' | '=' | '(' | ')' | '{' | '}' |
-        '[' | ']' | '&' | '>' | '<' => c,
-        '\n' => '␊',
-        '\r' => '␍',
-        '\0' => '␀',
-        //c => c,
-        _ => '�',
+impl<'a> BitBuilder<'a> {
+    pub fn index(&self) -> usize { self.index }
+
+    pub fn field<S: Into<String>>(&mut self, len: usize, ty: BitTy, tag: S) -> u64 {
+        assert!(len <= 64, "Bit field len {} exceeds 64 bits", len);
+        assert!(self.index + len <= self.buf.len() * 8, "Too big bit len {} exceeds buf len {} (bit index {})", len, self.buf.len(), self.index);
+        let value = read_bits(self.buf, self.index, len);
+        self.bits.push(BitField { offset: self.index, len, ty, tag: tag.into() });
+        self.index += len;
+        value
     }
 }
 
@@ -144,350 +512,979 @@ fn read_num<E: ByteOrder>(buf: &[u8]) -> u64 {
         1 => buf[0] as u64,
         2 => E::read_u16(buf) as u64,
         4 => E::read_u32(buf) as u64,
-        8 => E::read_u64(buf) as u64,
+        8 => E::read_u64(buf),
         len => panic!("Invalid buf len for **Num segment: {}", len),
     }
 }
 
-pub struct TermPrinter {
-    buf: Vec<u8>,
+fn read_endian(endian: Endian, buf: &[u8]) -> u64 {
+    match endian {
+        Endian::Big => read_num::<BigEndian>(buf),
+        Endian::Little => read_num::<LittleEndian>(buf),
+    }
+}
+
+fn format_varint(ty: &Ty, buf: &[u8]) -> String {
+    let value = match *ty {
+        Ty::Uleb128 => varint::read_uleb128(buf).map(|(n, _)| n.to_string()),
+        Ty::Sleb128 => varint::read_sleb128(buf).map(|(n, _)| n.to_string()),
+        Ty::ZigZag => varint::read_zigzag(buf).map(|(n, _)| n.to_string()),
+        Ty::Vlq => varint::read_vlq(buf).map(|(n, _)| n.to_string()),
+        _ => unreachable!(),
+    };
+    value.unwrap_or_else(|| "<truncated>".to_string())
+}
+
+/// Sorts the marks and merges overlapping ones, so that their ends are ordered too
+fn merge_marks(mut marks: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    marks.sort_by_key(|mark| mark.start);
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(marks.len());
+    for mark in marks {
+        match merged.last_mut() {
+            Some(last) if mark.start <= last.end => last.end = last.end.max(mark.end),
+            _ => merged.push(mark),
+        }
+    }
+    merged
+}
+
+/// Whether any of the merged `marks` overlaps `start..end`. Only the first mark ending after
+/// `start` can overlap, as the marks are sorted and don't overlap each other.
+fn is_marked(marks: &[Range<usize>], start: usize, end: usize) -> bool {
+    let i = marks.partition_point(|mark| mark.end <= start);
+    marks.get(i).is_some_and(|mark| mark.start < end)
+}
+
+pub struct TermPrinter<B: AsRef<[u8]> = Vec<u8>> {
+    buf: B,
     main: Segment,
+    charset: CharSet,
+    layout: Layout,
+    theme: Theme,
+    color_support: ColorSupport,
+    filter: Filter,
+    marks: Vec<Range<usize>>,
+    stats: bool,
 }
 
-impl TermPrinter {
-    pub fn new(buf: Vec<u8>) -> Self {
+impl<B: AsRef<[u8]>> TermPrinter<B> {
+    /// `buf` can be anything holding bytes, like a `Vec<u8>`, a borrowed slice or a mapped file
+    pub fn new(buf: B) -> Self {
         TermPrinter {
-            buf: buf,
-            main: Segment {
-                ty: Ty::Ascii,
-                kind: SegmentKind::Main,
-                childs: HashMap::new(),
-            },
+            buf,
+            main: Segment::new(0, Ty::Ascii, SegmentKind::Main),
+            charset: CharSet::Ascii,
+            layout: Layout::default(),
+            theme: Theme::default(),
+            color_support: ColorSupport::detect(),
+            filter: Filter::default(),
+            marks: Vec::new(),
+            stats: false,
         }
     }
 
-    pub fn style_builder(&mut self) -> StyleBuilder {
-        StyleBuilder {
-            buf: &*self.buf,
-            childs: &mut self.main.childs,
-            part_color: Color::White,
-            index: 0,
+    /// Set the character set used to render `Ty::Ascii` segments
+    pub fn set_charset(&mut self, charset: CharSet) {
+        self.charset = charset;
+    }
+
+    pub fn set_layout(&mut self, layout: Layout) {
+        self.layout = layout;
+    }
+
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+    }
+
+    /// Print the entropy and other byte statistics below every header, block and unstyled part
+    pub fn set_stats(&mut self, stats: bool) {
+        self.stats = stats;
+    }
+
+    /// Colors the terminal can show are detected by default. Other colors are replaced by the
+    /// nearest supported one.
+    pub fn set_color_support(&mut self, color_support: ColorSupport) {
+        self.color_support = color_support;
+    }
+
+    /// Print only the part of the file selected by `filter`
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.filter = filter;
+    }
+
+    /// Highlights the bytes in the absolute ranges `marks`, like the matches of a search.
+    /// Aligned rows aren't highlighted.
+    pub fn set_marks(&mut self, marks: Vec<Range<usize>>) {
+        self.marks = merge_marks(marks);
+    }
+
+    fn is_marked(&self, start: usize, end: usize) -> bool {
+        is_marked(&self.marks, start, end)
+    }
+
+    pub fn style_builder(&mut self) -> StyleBuilder<'_> {
+        StyleBuilder::new(self.buf.as_ref(), 0, &mut self.main.childs)
+    }
+
+    /// The styled segments, covering the whole buffer
+    pub fn root(&self) -> SegmentRef<'_> {
+        SegmentRef::new(self.buf.as_ref(), &self.main)
+    }
+
+    fn print_color(&self, color: Color, background: bool) {
+        fn print<C: color::Color>(c: C, background: bool) {
+            if background { print!("{}", color::Bg(c)) } else { print!("{}", color::Fg(c)) }
+        }
+        match color.downgrade(self.color_support) {
+            Color::Black => print(color::Black, background),
+            Color::Blue => print(color::Blue, background),
+            Color::Cyan => print(color::Cyan, background),
+            Color::Green => print(color::Green, background),
+            Color::Magenta => print(color::Magenta, background),
+            Color::Red => print(color::Red, background),
+            Color::Yellow => print(color::Yellow, background),
+            Color::White => print(color::White, background),
+            Color::Fixed(n) => print(color::AnsiValue(n), background),
+            Color::Rgb(r, g, b) => print(color::Rgb(r, g, b), background),
         }
     }
 
-    fn print_hex_line(buf: &[u8]) {
-        assert!(buf.len() <= 32);
-        let mut num = 0;
-        for b in buf.iter() {
-            if num % 8 == 0 {
-                print!(" ");
-            }
-            num += 1;
-            print!("{:02X} ", b);
+    fn print_reset(&self) {
+        if self.color_support != ColorSupport::None {
+            print!("{}", style::Reset);
         }
-        while num < 32 {
-            if num == 16 || num == 24 {
-                break;
-            }
-            if num % 8 == 0 {
-                print!(" ");
-            }
-            num += 1;
-            print!(".. ");
+    }
+
+    fn print_style(&self, style: Style) {
+        if self.color_support == ColorSupport::None {
+            return;
+        }
+        print!("{}", style::Reset);
+        self.print_color(style.fg, false);
+        if let Some(bg) = style.bg {
+            self.print_color(bg, true);
         }
+        if style.bold {
+            print!("{}", style::Bold);
+        }
+        if style.underline {
+            print!("{}", style::Underline);
+        }
+        if style.dim {
+            print!("{}", style::Faint);
+        }
+    }
+
+    fn print_text(&self, cells: &[char]) {
+        print!("| {} |", self.layout.text_row(cells))
     }
 
-    fn print_extras(chunk: &[u8], seg: &Segment) {
+    /// Text column and value of the row at `start` of the segment bytes `buf`. `cells` are the
+    /// text cells of the row, see `text_cells`. Values are decoded from the whole segment and
+    /// only printed on its first row.
+    fn print_extras(&self, buf: &[u8], start: usize, cells: Option<&[char]>, seg: &Segment) {
+        if let Some(cells) = cells {
+            self.print_text(cells);
+        }
         match seg.ty {
-            Ty::Ascii => {
-                let text_iter = chunk
-                    .iter()
-                    .map(|&c| make_ascii(c as char))
-                    .pad_using(32, |_| '.')
-                    .enumerate();
-                let mut text = String::with_capacity(40);
-                for (i, c) in text_iter {
-                    text.push(c);
-                    if c == '�' {
-                        text.push(' ');
-                    }
-                    if (i + 1) % 8 == 0 {
-                        text.push(' ');
-                    }
-                    if i == 15 {
-                        text.push(' ');
-                    }
+            Ty::Ascii | Ty::Binary => {}
+            Ty::Text(encoding) => {
+                if start == 0 {
+                    print!(" {:?}", encoding.decode(buf));
                 }
-                print!("| {}|", text)
-            }
-            Ty::Binary => {}
-            Ty::BeNum => {
-                print!(": {}", read_num::<BigEndian>(chunk));
-            }
-            Ty::LeNum => {
-                print!(": {}", read_num::<LittleEndian>(chunk));
-            }
-            Ty::Ip4 => {
-                assert!(chunk.len() == 4, "Wrong len for ipv4 addr");
-                print!("{}.{}.{}.{}", chunk[0], chunk[1], chunk[2], chunk[3]);
-            }
-            Ty::Custom(ref custom) => {
-                let num = match chunk.len() {
-                    1 | 2 | 4 | 8 => Some(read_num::<LittleEndian>(chunk)),
-                    _ => None,
-                };
-                print!("; {} ({})", custom, num.map(|n|n.to_string()).as_ref().map(|s|s as &str).unwrap_or(""));
             }
+            _ if start != 0 => {}
+            Ty::Ip4 => print!("{}", format_value(&seg.ty, buf).unwrap()),
+            Ty::Custom(ref custom) => print!("; {}", custom),
+            _ => print!(": {}", format_value(&seg.ty, buf).unwrap()),
         }
     }
 
-    fn print_segment(buf: &[u8], s: Segment) {
-        use std::cmp::Ord;
-
-        if s.childs.is_empty() {
-            for c in buf.iter().chunks(32).into_iter() {
-                let chunk = c.cloned().collect::<Vec<u8>>();
-
+    fn print_segment(&self, buf: &[u8], s: &Segment) {
+        let childs = s.expand(buf);
+        if childs.is_empty() {
+            let row_len = self.layout.row_len();
+            let format = s.cell.unwrap_or(self.layout.cell);
+            let cells = text_cells(&s.ty, self.charset, buf);
+            for (i, chunk) in buf.chunks(row_len).enumerate() {
+                let row_cells = cells.as_ref().map(|cells| &cells[i * row_len..i * row_len + chunk.len()]);
+                if !self.filter.keeps_row(s, s.start + i * row_len, s.start + i * row_len + chunk.len()) {
+                    continue;
+                }
+                let marked = self.is_marked(s.start + i * row_len, s.start + i * row_len + chunk.len());
                 match s.kind {
-                    SegmentKind::Line { ref tag, color } => {
-                        match color {
-                            Color::Blue => print!("{}", Fg(Blue)),
-                            Color::Cyan => print!("{}", Fg(Cyan)),
-                            Color::Green => print!("{}", Fg(Green)),
-                            Color::Magenta => print!("{}", Fg(Magenta)),
-                            Color::Red => print!("{}", Fg(Red)),
-                            Color::Yellow => print!("{}", Fg(Yellow)),
-                            Color::White => print!("{}", Fg(White)),
-                        }
-                        TermPrinter::print_hex_line(&chunk);
+                    SegmentKind::Line { ref tag, paint } => {
+                        let style = if is_unknown_value(&s.ty, buf) { self.theme.error } else { self.theme.resolve(paint) };
+                        self.print_style(if marked { style.on(self.theme.mark) } else { style });
+                        print!("{}", self.layout.offset_column(s.start + i * row_len));
+                        print!("{}", self.layout.hex_row(format, chunk));
                         print!("  {:>12} ", tag);
                     }
                     _ => {
-                        TermPrinter::print_hex_line(&chunk);
+                        if marked {
+                            self.print_style(Style::fg(Color::White).on(self.theme.mark));
+                        }
+                        print!("{}", self.layout.offset_column(s.start + i * row_len));
+                        print!("{}", self.layout.hex_row(format, chunk));
                         print!("          ");
                     }
                 }
-                TermPrinter::print_extras(&chunk, &s);
-                println!("{}", Fg(Reset));
+                self.print_extras(buf, i * row_len, row_cells, s);
+                self.print_reset();
+                println!();
+            }
+            if let SegmentKind::Line { paint, .. } = s.kind {
+                for field in &s.bits {
+                    let (diagram, value, known) = format_bits(buf, field);
+                    self.print_style(if known { self.theme.resolve(paint) } else { self.theme.error });
+                    print!("{}", self.layout.offset_padding());
+                    print!("{:<width$}", format!(" {}", diagram), width = self.layout.hex_row_width(format));
+                    print!("  {:>12} : {}", field.tag, value);
+                    self.print_reset();
+                    println!();
+                }
             }
         } else {
-            let mut segments = s.childs.into_iter().collect::<Vec<_>>();
-            segments.sort_by(|a, b| (a.0).0.cmp(&(b.0).0));
-
-            for ((begin, end), seg) in segments.into_iter() {
-                let buf = &buf[begin..end];
-                TermPrinter::print_segment(buf, seg.clone());
-                match seg.kind {
-                    SegmentKind::Header | SegmentKind::Block => println!(),
-                    SegmentKind::Line { .. } => {}
-                    SegmentKind::Main => panic!(),
+            for (&(begin, end), seg) in childs {
+                if self.filter.overlaps(seg.start, seg.start + end - begin) {
+                    self.print_child(&buf[begin..end], seg);
                 }
             }
         }
+        if self.stats && !matches!(s.kind, SegmentKind::Line { .. }) {
+            self.print_style(self.theme.payload);
+            print!("{}  {}", self.layout.offset_padding(), Stats::new(buf).summary());
+            self.print_reset();
+            println!();
+        }
+    }
+
+    fn print_child(&self, buf: &[u8], seg: &Segment) {
+        self.print_segment(buf, seg);
+        match seg.kind {
+            SegmentKind::Header | SegmentKind::Block => println!(),
+            SegmentKind::Line { .. } => {}
+            SegmentKind::Main => panic!(),
+        }
+    }
+
+    /// Prints the top-level segments selected by the filter. `first_block` is the index of the
+    /// first block of `main` among all blocks.
+    fn print_main(&self, buf: &[u8], main: &Segment, first_block: usize) {
+        if main.expand(buf).is_empty() {
+            self.print_segment(buf, main);
+            return;
+        }
+        let tops = self.filter.tops(buf, main, first_block).collect::<Vec<_>>();
+        expand_all(&tops);
+        for (buf, seg) in tops {
+            self.print_child(buf, seg);
+        }
+    }
+
+    fn print_spans(&self, spans: &[aligned::Span]) {
+        for span in spans {
+            match span.style {
+                Some(style) => self.print_style(style),
+                None => self.print_reset(),
+            }
+            print!("{}", span.text);
+        }
+        self.print_reset();
+    }
+
+    fn print_aligned_row(&self, row: aligned::Row) {
+        print!("{}", self.layout.offset_column(row.offset));
+        self.print_spans(&row.hex);
+        print!(" | ");
+        self.print_spans(&row.text);
+        print!(" |  ");
+        self.print_spans(&row.legend);
+        println!();
     }
 
     pub fn print(self) {
-        let TermPrinter { buf, main } = self;
-        TermPrinter::print_segment(&buf, main);
+        if self.layout.aligned {
+            aligned::for_each_row(self.buf.as_ref(), &self.main, &self.layout, self.charset, &self.theme, &self.filter, |row| self.print_aligned_row(row));
+        } else {
+            self.print_main(self.buf.as_ref(), &self.main, 0);
+        }
     }
 }
 
-pub struct HtmlPrinter {
-    buf: Vec<u8>,
+pub struct HtmlPrinter<B: AsRef<[u8]> = Vec<u8>> {
+    buf: B,
     main: Segment,
+    charset: CharSet,
+    layout: Layout,
+    theme: Theme,
+    filter: Filter,
+    marks: Vec<Range<usize>>,
+    /// Window size of the entropy heatmap
+    stats: Option<usize>,
 }
 
-impl HtmlPrinter {
-    pub fn new(buf: Vec<u8>) -> Self {
+impl<B: AsRef<[u8]>> HtmlPrinter<B> {
+    /// See `TermPrinter::new`
+    pub fn new(buf: B) -> Self {
         HtmlPrinter {
-            buf: buf,
-            main: Segment {
-                ty: Ty::Ascii,
-                kind: SegmentKind::Main,
-                childs: HashMap::new(),
-            },
+            buf,
+            main: Segment::new(0, Ty::Ascii, SegmentKind::Main),
+            charset: CharSet::Ascii,
+            layout: Layout::default(),
+            theme: Theme::default(),
+            filter: Filter::default(),
+            marks: Vec::new(),
+            stats: None,
         }
     }
 
-    pub fn style_builder(&mut self) -> StyleBuilder {
-        StyleBuilder {
-            buf: &*self.buf,
-            childs: &mut self.main.childs,
-            part_color: Color::White,
-            index: 0,
+    /// Set the character set used to render `Ty::Ascii` segments
+    pub fn set_charset(&mut self, charset: CharSet) {
+        self.charset = charset;
+    }
+
+    pub fn set_layout(&mut self, layout: Layout) {
+        self.layout = layout;
+    }
+
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+    }
+
+    /// Show a sidebar with the entropy of every `window` bytes of the printed headers and blocks
+    /// as a heatmap and a histogram of all printed bytes
+    pub fn set_stats(&mut self, window: Option<usize>) {
+        self.stats = window;
+    }
+
+    /// Print only the part of the file selected by `filter`
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.filter = filter;
+    }
+
+    /// Highlights the bytes in the absolute ranges `marks`, like the matches of a search.
+    /// Aligned rows aren't highlighted.
+    pub fn set_marks(&mut self, marks: Vec<Range<usize>>) {
+        self.marks = merge_marks(marks);
+    }
+
+    fn is_marked(&self, start: usize, end: usize) -> bool {
+        is_marked(&self.marks, start, end)
+    }
+
+    pub fn style_builder(&mut self) -> StyleBuilder<'_> {
+        StyleBuilder::new(self.buf.as_ref(), 0, &mut self.main.childs)
+    }
+
+    /// The styled segments, covering the whole buffer
+    pub fn root(&self) -> SegmentRef<'_> {
+        SegmentRef::new(self.buf.as_ref(), &self.main)
+    }
+
+    fn css_color(color: Color) -> String {
+        match color {
+            Color::Black => "black".to_string(),
+            Color::Blue => "blue".to_string(),
+            Color::Cyan => "cyan".to_string(),
+            Color::Green => "green".to_string(),
+            Color::Magenta => "magenta".to_string(),
+            Color::Red => "red".to_string(),
+            Color::Yellow => "yellow".to_string(),
+            Color::White => "white".to_string(),
+            Color::Fixed(_) | Color::Rgb(..) => {
+                let (r, g, b) = color.rgb();
+                format!("#{:02x}{:02x}{:02x}", r, g, b)
+            }
         }
     }
 
-    fn make_ascii(c: char) -> char {
-        match c {
-            'a'...'z' | 'A'...'Z' | '0'...'9' | ':' | ';' | '@' | '/' | '\\' | '|' | '?' |
-            '!' | '+' | '*' | '.' | ',' | ' ' | '-' | '_' | '\'' | '"' | '=' | '(' | ')' |
-            '{' | '}' | '[' | ']' | '&' | '>' | '<' => c,
-            '\n' => '␊',
-            '\r' => '␍',
-            '\0' => '␀',
-            //c => c,
-            _ => '�',
+    fn css_style(style: Style) -> String {
+        let mut css = format!("color: {}", Self::css_color(style.fg));
+        if let Some(bg) = style.bg {
+            css.push_str(&format!("; background: {}", Self::css_color(bg)));
+        }
+        if style.bold {
+            css.push_str("; font-weight: bold");
+        }
+        if style.underline {
+            css.push_str("; text-decoration: underline");
         }
+        if style.dim {
+            css.push_str("; opacity: 0.6");
+        }
+        css
     }
 
-    fn print_hex_line(buf: &[u8]) {
-        assert!(buf.len() <= 32);
-        print!("<code class='hex'>");
-        let mut num = 0;
-        for b in buf.iter() {
-            if num == 16 {
-                print!(" ");
-            } else if num % 8 == 0 {
-                print!(" ");
+    /// Escapes text for use in element content and quoted attributes
+    fn escape(text: &str) -> String {
+        let mut escaped = String::with_capacity(text.len());
+        for c in text.chars() {
+            match c {
+                '&' => escaped.push_str("&amp;"),
+                '<' => escaped.push_str("&lt;"),
+                '>' => escaped.push_str("&gt;"),
+                '"' => escaped.push_str("&quot;"),
+                '\'' => escaped.push_str("&#39;"),
+                c => escaped.push(c),
             }
-            num += 1;
-            print!("{:02X} ", b);
         }
-        print!("</code>");
+        escaped
+    }
+
+    /// Anchor of a header or block, nested blocks may start at the same offset
+    fn id(seg: &Segment, depth: usize) -> String {
+        format!("seg-{}-{}", seg.start, depth)
+    }
+
+    /// Title of a header or block in its summary and in the outline
+    fn summary(buf: &[u8], seg: &Segment) -> String {
+        let kind = match seg.kind {
+            SegmentKind::Header => "header",
+            _ => "block",
+        };
+        let mut summary = format!("{} 0x{:X}", kind, seg.start);
+        let first = seg.expand(buf).iter().next();
+        if let Some((&(begin, end), &Segment { kind: SegmentKind::Line { ref tag, .. }, ref ty, .. })) = first {
+            if let Some(value) = format_value(ty, &buf[begin..end]) {
+                summary.push_str(&format!(" {}: {}", tag, value));
+            }
+        }
+        summary
+    }
+
+    /// Appends links to the headers and blocks below `seg` to `outline`
+    fn outline(&self, buf: &[u8], seg: &Segment, depth: usize, outline: &mut String) {
+        for (&(begin, end), child) in seg.expand(buf) {
+            if self.filter.overlaps(child.start, child.start + end - begin) {
+                self.outline_entry(&buf[begin..end], child, depth, outline);
+            }
+        }
+    }
+
+    fn outline_entry(&self, buf: &[u8], seg: &Segment, depth: usize, outline: &mut String) {
+        if let SegmentKind::Header | SegmentKind::Block = seg.kind {
+            outline.push_str(&format!("<a href='#{}' style='padding-left: {}em'>{}</a>\n", Self::id(seg, depth), depth, Self::escape(&Self::summary(buf, seg))));
+            self.outline(buf, seg, depth + 1, outline);
+        }
+    }
+
+    /// Outline of the top-level segments selected by the filter, see `print_main`
+    fn main_outline(&self, buf: &[u8], main: &Segment, first_block: usize, outline: &mut String) {
+        for (buf, seg) in self.filter.tops(buf, main, first_block) {
+            self.outline_entry(buf, seg, 0, outline);
+        }
+    }
+
+    /// Appends a heatmap cell for every window of the top-level segments selected by the filter
+    /// to `heatmap` and adds their bytes to `total`. Windows don't cross segments.
+    fn heatmap(&self, buf: &[u8], main: &Segment, first_block: usize, heatmap: &mut String, total: &mut Stats) {
+        let window = match self.stats {
+            Some(window) => window,
+            None => return,
+        };
+        let parts = if main.expand(buf).is_empty() {
+            vec![(buf, main.start, None)]
+        } else {
+            self.filter.tops(buf, main, first_block).map(|(buf, seg)| (buf, seg.start, Some(Self::id(seg, 0)))).collect()
+        };
+        for (buf, start, link) in parts {
+            total.add(buf);
+            for (i, chunk) in buf.chunks(window).enumerate() {
+                let stats = Stats::new(chunk);
+                // Blue for low entropy to red for random data
+                let style = format!("background: hsl({:.0}, 100%, 45%)", 240.0 - stats.entropy() * 30.0);
+                let title = Self::escape(&format!("0x{:X}: {}", start + i * window, stats.summary()));
+                match link {
+                    Some(ref link) => heatmap.push_str(&format!("<a class='heat' href='#{}' style='{}' title='{}'></a>\n", link, style, title)),
+                    None => heatmap.push_str(&format!("<span class='heat' style='{}' title='{}'></span>\n", style, title)),
+                }
+            }
+        }
+    }
+
+    fn print_stats(heatmap: &str, total: &Stats) {
+        println!("<aside id='stats'><div id='histogram' title='{}'>", Self::escape(&total.summary()));
+        let max = total.histogram.iter().cloned().max().unwrap_or(0).max(1);
+        for &count in total.histogram.iter() {
+            print!("<i style='width: {:.1}%'></i>", count as f64 * 100.0 / max as f64);
+        }
+        println!("</div>");
+        print!("{}", heatmap);
+        println!("</aside>");
     }
 
-    fn print_extras(chunk: &[u8], seg: &Segment) {
+    fn print_hex_line(&self, offset: usize, format: CellFormat, chunk: &[u8]) {
+        print!("<code class='offset'>{}</code>", self.layout.offset_column(offset));
+        print!("<code class='hex'>{}</code>", Self::escape(&self.layout.hex_row(format, chunk)));
+    }
+
+    fn print_text(&self, cells: &[char]) {
+        print!("|{}|", Self::escape(&self.layout.text_row(cells)))
+    }
+
+    /// See `TermPrinter::print_extras`
+    fn print_extras(&self, buf: &[u8], start: usize, cells: Option<&[char]>, seg: &Segment) {
         print!("<code>");
+        if let Some(cells) = cells {
+            self.print_text(cells);
+        }
         match seg.ty {
-            Ty::Ascii => {
-                let text_iter = chunk
-                    .iter()
-                    .map(|&c| HtmlPrinter::make_ascii(c as char))
-                    .pad_using(32, |_| '.')
-                    .enumerate();
-                let mut text = String::with_capacity(40);
-                for (i, c) in text_iter {
-                    text.push(c);
-                    if (i + 1) % 8 == 0 {
-                        text.push(' ');
-                    }
-                    if i == 15 {
-                        text.push(' ');
-                    }
+            Ty::Ascii | Ty::Binary => {}
+            Ty::Text(encoding) => {
+                if start == 0 {
+                    print!(" {}", Self::escape(&format!("{:?}", encoding.decode(buf))));
                 }
-                print!("|{}|", text)
-            }
-            Ty::Binary => {},
-            Ty::BeNum => {
-                let num = match chunk.len() {
-                    1 => chunk[0] as u64,
-                    2 => BigEndian::read_u16(chunk) as u64,
-                    4 => BigEndian::read_u32(chunk) as u64,
-                    8 => BigEndian::read_u64(chunk) as u64,
-                    len => panic!("Invalid buf len for BeNum segment: {}", len),
-                };
-                print!(": {}", num);
             }
-            Ty::LeNum => {
-                let num = match chunk.len() {
-                    1 => chunk[0] as u64,
-                    2 => LittleEndian::read_u16(chunk) as u64,
-                    4 => LittleEndian::read_u32(chunk) as u64,
-                    8 => LittleEndian::read_u64(chunk) as u64,
-                    len => panic!("Invalid buf len for LeNum segment: {}", len),
-                };
-                print!(": {}", num);
+            _ if start != 0 => {}
+            Ty::Ip4 => print!("{}", format_value(&seg.ty, buf).unwrap()),
+            Ty::Custom(ref custom) => print!("; {}", Self::escape(custom)),
+            _ => print!(": {}", Self::escape(&format_value(&seg.ty, buf).unwrap())),
+        }
+        print!("</code>");
+    }
+
+    /// Attributes shown when hovering a segment
+    fn data_attrs(buf: &[u8], s: &Segment) -> String {
+        let mut attrs = format!(" data-range='0x{:X}..0x{:X}' data-ty='{}'", s.start, s.start + buf.len(), Self::escape(&ty_name(&s.ty)));
+        if let SegmentKind::Line { ref tag, .. } = s.kind {
+            attrs.push_str(&format!(" data-tag='{}'", Self::escape(tag)));
+        }
+        if let Some(value) = format_value(&s.ty, buf) {
+            attrs.push_str(&format!(" data-value='{}'", Self::escape(&value)));
+        }
+        attrs
+    }
+
+    /// Rows of the bytes of a segment which isn't split into children
+    fn print_rows(&self, buf: &[u8], s: &Segment) {
+        let class = if self.is_marked(s.start, s.start + buf.len()) { "seg match" } else { "seg" };
+        print!("<div class='{}'{}>", class, Self::data_attrs(buf, s));
+        let row_len = self.layout.row_len();
+        let format = s.cell.unwrap_or(self.layout.cell);
+        let cells = text_cells(&s.ty, self.charset, buf);
+        for (i, chunk) in buf.chunks(row_len).enumerate() {
+            let row_cells = cells.as_ref().map(|cells| &cells[i * row_len..i * row_len + chunk.len()]);
+            if !self.filter.keeps_row(s, s.start + i * row_len, s.start + i * row_len + chunk.len()) {
+                continue;
             }
-            Ty::Ip4 => {
-                print!("{}.{}.{}.{}", chunk[0], chunk[1], chunk[2], chunk[3]);
+            match s.kind {
+                SegmentKind::Line { ref tag, paint } => {
+                    let style = if is_unknown_value(&s.ty, buf) { self.theme.error } else { self.theme.resolve(paint) };
+                    print!("<div class='line' style='{}'>", Self::css_style(style));
+                    self.print_hex_line(s.start + i * row_len, format, chunk);
+                    print!("<span>  {:>8} </span>", Self::escape(tag));
+                    self.print_extras(buf, i * row_len, row_cells, s);
+                    println!("</div>");
+                }
+                _ => {
+                    print!("<div class='line' style='color: {}'>", match s.ty {
+                        Ty::Ascii | Ty::Text(_) => "magenta",
+                        Ty::Binary => "",
+                        Ty::BeNum | Ty::LeNum => "cyan",
+                        Ty::Uleb128 | Ty::Sleb128 | Ty::ZigZag | Ty::Vlq => "cyan",
+                        Ty::Ip4 => "green",
+                        Ty::Enum(..) | Ty::Flags(..) => "yellow",
+                        Ty::Custom(_) => "yellow",
+                    });
+                    self.print_hex_line(s.start + i * row_len, format, chunk);
+                    print!("<span>           </span>");
+                    self.print_extras(buf, i * row_len, row_cells, s);
+                    println!("</div>");
+                }
             }
-            Ty::Custom(ref custom) => {
-                print!("; {}", custom);
+        }
+        if let SegmentKind::Line { paint, .. } = s.kind {
+            for field in &s.bits {
+                let (diagram, value, known) = format_bits(buf, field);
+                let style = if known { self.theme.resolve(paint) } else { self.theme.error };
+                print!("<div class='line bits' style='{}'>", Self::css_style(style));
+                print!("<code class='offset'>{}</code>", self.layout.offset_padding());
+                print!("<code class='hex'>{:<width$}</code>", format!(" {}", diagram), width = self.layout.hex_row_width(format));
+                print!("<span>  {:>8} </span>", Self::escape(&field.tag));
+                print!("<code>: {}</code>", Self::escape(&value));
+                println!("</div>");
             }
         }
-        print!("</code>");
+        print!("</div>");
     }
 
-    fn print_segment(buf: &[u8], s: Segment) {
-        use std::cmp::Ord;
+    /// Lazy blocks are collapsed, unless they contain a mark
+    fn is_collapsed(&self, buf: &[u8], s: &Segment) -> bool {
+        s.lazy.is_some() && !self.is_marked(s.start, s.start + buf.len())
+    }
 
-        print!("<div>");
-        if s.childs.is_empty() {
-            for c in buf.iter().chunks(32).into_iter() {
-                let chunk = c.cloned().collect::<Vec<u8>>();
-                match s.kind {
-                    SegmentKind::Line { ref tag, color } => {
-                        print!("<div class='line' style='color: {}'>", match color {
-                            Color::Blue => "blue",
-                            Color::Cyan => "cyan",
-                            Color::Green => "green",
-                            Color::Magenta => "magenta",
-                            Color::Red => "red",
-                            Color::Yellow => "yellow",
-                            Color::White => "white",
-                        });
-                        HtmlPrinter::print_hex_line(&chunk);
-                        print!("<span>  {:>8} </span>", tag);
-                        HtmlPrinter::print_extras(&chunk, &s);
-                        println!("</div>");
-                    }
-                    _ => {
-                        print!("<div class='line' style='color: {}'>", match s.ty {
-                            Ty::Ascii => "magenta",
-                            Ty::Binary => "",
-                            Ty::BeNum | Ty::LeNum => "cyan",
-                            Ty::Ip4 => "green",
-                            Ty::Custom(_) => "yellow",
-                        });
-                        HtmlPrinter::print_hex_line(&chunk);
-                        print!("<span>           </span>");
-                        HtmlPrinter::print_extras(&chunk, &s);
-                        println!("</div>");
-                    }
+    /// `depth` counts the headers and blocks around `s`
+    fn print_segment(&self, buf: &[u8], s: &Segment, depth: usize) {
+        let childs = s.expand(buf);
+        if childs.is_empty() {
+            self.print_rows(buf, s);
+        } else {
+            let collapsible = match s.kind {
+                SegmentKind::Header | SegmentKind::Block => true,
+                SegmentKind::Line { .. } | SegmentKind::Main => false,
+            };
+            if collapsible {
+                let open = if self.is_collapsed(buf, s) { "" } else { " open" };
+                println!("<details{} id='{}'><summary>{}</summary>", open, Self::id(s, depth), Self::escape(&Self::summary(buf, s)));
+            }
+            for (&(begin, end), seg) in childs {
+                if self.filter.overlaps(seg.start, seg.start + end - begin) {
+                    self.print_segment(&buf[begin..end], seg, depth + 1);
                 }
             }
+            if collapsible {
+                println!("</details>");
+            }
+        }
+    }
+
+    /// See `TermPrinter::print_main`
+    fn print_main(&self, buf: &[u8], main: &Segment, first_block: usize) {
+        if main.expand(buf).is_empty() {
+            self.print_segment(buf, main, 0);
+            return;
+        }
+        let tops = self.filter.tops(buf, main, first_block).collect::<Vec<_>>();
+        expand_all(&tops);
+        for (buf, seg) in tops {
+            self.print_segment(buf, seg, 0);
+        }
+    }
+
+    fn print_spans(spans: &[aligned::Span]) {
+        for span in spans {
+            match span.style {
+                Some(style) => print!("<span style='{}'>{}</span>", Self::css_style(style), Self::escape(&span.text)),
+                None => print!("{}", Self::escape(&span.text)),
+            }
+        }
+    }
+
+    fn print_aligned_row(&self, row: aligned::Row) {
+        print!("<div class='line'><code class='offset'>{}</code><code class='hex'>", self.layout.offset_column(row.offset));
+        Self::print_spans(&row.hex);
+        print!("</code><code>|");
+        Self::print_spans(&row.text);
+        print!("|</code><code>");
+        Self::print_spans(&row.legend);
+        println!("</code></div>");
+    }
+
+    /// Prints a self-contained page: headers and blocks are collapsible and listed in an outline,
+    /// hovering a segment shows its details and the search box highlights matching segments.
+    fn print_head() {
+        println!("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>hex_view</title>");
+        println!("<style>{}</style>\n</head>\n<body>", HTML_STYLE);
+    }
+
+    fn print_outline(outline: &str) {
+        println!("<nav id='outline'><input id='search' type='search' placeholder='Search'>");
+        print!("{}", outline);
+        println!("</nav>");
+    }
+
+    fn print_tail() {
+        println!("<div id='info'></div>");
+        println!("<script>{}</script>\n</body>\n</html>", HTML_SCRIPT);
+    }
+
+    /// The outline follows the main part, so that it lists the blocks styled while printing
+    pub fn print(self) {
+        Self::print_head();
+        println!("<main>");
+        if self.layout.aligned {
+            aligned::for_each_row(self.buf.as_ref(), &self.main, &self.layout, self.charset, &self.theme, &self.filter, |row| self.print_aligned_row(row));
         } else {
-            let mut segments = s.childs.into_iter().collect::<Vec<_>>();
-            segments.sort_by(|a, b| (a.0).0.cmp(&(b.0).0));
-
-            for ((begin, end), seg) in segments.into_iter() {
-                let buf = &buf[begin..end];
-                HtmlPrinter::print_segment(buf, seg.clone());
-                match seg.kind {
-                    SegmentKind::Header | SegmentKind::Block => println!("<br>"),
-                    SegmentKind::Line { .. } => {}
-                    SegmentKind::Main => panic!(),
+            self.print_main(self.buf.as_ref(), &self.main, 0);
+        }
+        println!("</main>");
+        let mut outline = String::new();
+        self.main_outline(self.buf.as_ref(), &self.main, 0, &mut outline);
+        Self::print_outline(&outline);
+        let mut heatmap = String::new();
+        let mut total = Stats::new(&[]);
+        self.heatmap(self.buf.as_ref(), &self.main, 0, &mut heatmap, &mut total);
+        if self.stats.is_some() {
+            Self::print_stats(&heatmap, &total);
+        }
+        Self::print_tail();
+    }
+}
+
+const HTML_STYLE: &str = "
+body { margin: 0; font-family: monospace; font-size: 15px; background: black; color: yellowgreen; }
+code { white-space: pre; margin-right: 10px; }
+nav { position: fixed; top: 0; bottom: 0; left: 0; width: 18em; overflow: auto; padding: 8px; border-right: 1px solid #333; }
+nav a { display: block; color: #aaa; text-decoration: none; white-space: nowrap; }
+nav a:hover { color: white; }
+nav input { width: 100%; margin-bottom: 8px; background: #111; color: white; border: 1px solid #444; }
+main { margin-left: 19em; padding: 8px; padding-bottom: 3em; }
+details { margin-bottom: 1em; }
+summary { cursor: pointer; color: #888; }
+.seg.hover { background: #222; }
+.seg.match { background: #442; }
+body:has(#stats) main { margin-right: 6em; }
+#stats { position: fixed; top: 0; right: 0; bottom: 2em; width: 5em; overflow: auto; padding: 4px; border-left: 1px solid #333; }
+#histogram { margin-bottom: 8px; }
+#histogram i { display: block; height: 1px; background: #888; }
+.heat { display: block; height: 4px; }
+#info { position: fixed; left: 19em; right: 0; bottom: 0; padding: 4px 8px; background: #111; color: white; white-space: pre; }
+";
+
+const HTML_SCRIPT: &str = "
+var info = document.getElementById('info');
+document.querySelectorAll('.seg').forEach(function (seg) {
+    seg.addEventListener('mouseenter', function () {
+        seg.classList.add('hover');
+        var d = seg.dataset;
+        info.textContent = (d.tag ? d.tag + '  ' : '') + d.range + '  ' + d.ty + (d.value ? '  ' + d.value : '');
+    });
+    seg.addEventListener('mouseleave', function () {
+        seg.classList.remove('hover');
+        info.textContent = '';
+    });
+});
+document.getElementById('search').addEventListener('input', function (e) {
+    var query = e.target.value.toLowerCase();
+    var first = null;
+    document.querySelectorAll('.seg').forEach(function (seg) {
+        var d = seg.dataset;
+        var text = [d.tag, d.value, seg.textContent].join(' ').toLowerCase();
+        var match = query !== '' && text.indexOf(query) !== -1;
+        seg.classList.toggle('match', match);
+        if (match) {
+            for (var p = seg.parentElement; p; p = p.parentElement) {
+                if (p.tagName === 'DETAILS') p.open = true;
+            }
+            first = first || seg;
+        }
+    });
+    if (first) first.scrollIntoView({ block: 'center' });
+});
+";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block_styler(mut builder: StyleBuilder) {
+        builder.line(2, Ty::BeNum, "a");
+        builder.line(2, Ty::LeNum, "b");
+    }
+
+    /// Tree shared by the tests of all modules: a header with a "magic" line of `header_len`
+    /// bytes, followed by blocks of `block_len` bytes up to the end of `buf`. Blocks are split
+    /// into "port" lines of up to two bytes.
+    pub(crate) fn tree(buf: &[u8], header_len: usize, block_len: usize) -> Segment {
+        let mut main = Segment::new(0, Ty::Binary, SegmentKind::Main);
+        {
+            let mut builder = StyleBuilder::new(buf, 0, &mut main.childs);
+            builder.header(0, header_len, Ty::Binary).line(header_len, Ty::BeNum, "magic");
+            for begin in (header_len..buf.len()).step_by(block_len) {
+                let mut block = builder.block(begin, begin + block_len, Ty::Binary);
+                while block.index() < block_len {
+                    let len = (block_len - block.index()).min(2);
+                    block.line(len, Ty::BeNum, "port");
                 }
             }
         }
-        print!("</div>");
+        main
     }
 
-    pub fn print(mut self) {
-        println!("<html>
-<head>
-<meta charset=\"utf-8\">
-<style>
-    body {{
-        width: 100vw;
-        font-family: monospace;
-        font-size: 15px;
-        background: black;
-        color: yellowgreen;
-    }}
-    code {{
-        white-space: pre;
-        margin-right: 10px;
-    }}
-    .hex {{
-        display: inline-block;
-        width: 900px;
-    }}
-</style>
-</head>
-<body>");
-        HtmlPrinter::print_segment(
-            &self.buf,
-            std::mem::replace(
-                &mut self.main,
-                Segment {
-                    ty: Ty::Ascii,
-                    kind: SegmentKind::Main,
-                    childs: HashMap::new(),
-                },
-            ),
-        );
-        println!("</body>\n</html>");
+    fn dump(buf: &[u8], main: &Segment) -> Vec<(Range<usize>, String, Option<String>)> {
+        SegmentRef::new(buf, main).iter().map(|seg| (seg.range(), seg.name().to_string(), seg.value())).collect()
+    }
+
+    fn lines(ranges: &[(usize, usize)]) -> Segment {
+        let buf = [0; 8];
+        let mut main = Segment::new(0, Ty::Binary, SegmentKind::Main);
+        {
+            let mut builder = StyleBuilder::new(&buf, 0, &mut main.childs);
+            for &(begin, end) in ranges {
+                builder.block(begin, end, Ty::Binary);
+            }
+            builder.line(0, Ty::Binary, "empty");
+            builder.line_until(0, Ty::Binary, "empty");
+            builder.line(2, Ty::Binary, "a");
+            builder.line(0, Ty::Binary, "empty");
+        }
+        main
+    }
+
+    #[test]
+    fn empty_lines() {
+        let main = lines(&[]);
+        assert_eq!(main.childs.keys().cloned().collect::<Vec<_>>(), vec![(0, 2)]);
+    }
+
+    #[test]
+    fn adjacent_segments() {
+        let main = lines(&[(2, 4), (4, 8)]);
+        assert_eq!(main.childs.len(), 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "Segment 0..2 overlaps 1..3")]
+    fn overlapping_segments() {
+        lines(&[(1, 3)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Segment 2..4 was already added")]
+    fn duplicate_segments() {
+        lines(&[(2, 4), (2, 4)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Bit field line at index 2 is empty")]
+    fn empty_bitfield() {
+        let buf = [0; 4];
+        let mut main = Segment::new(0, Ty::Binary, SegmentKind::Main);
+        let mut builder = StyleBuilder::new(&buf, 0, &mut main.childs);
+        builder.line(2, Ty::Binary, "a");
+        builder.bitfield(0, Ty::Binary, "bits");
+    }
+
+    const FLAGS: NameTable = &[(0x0, "empty"), (0x1, "FIN"), (0x2, "SYN"), (0x10, "ACK")];
+
+    #[test]
+    fn enums() {
+        let names: NameTable = &[(1, "one"), (0x86DD, "IPv6")];
+        assert_eq!(format_enum(names, 0x86DD, 4), ("IPv6 (0x86DD)".to_string(), true));
+        assert_eq!(format_enum(names, 1, 2), ("one (0x01)".to_string(), true));
+        assert_eq!(format_enum(names, 2, 2), ("<unknown> (0x02)".to_string(), false));
+    }
+
+    #[test]
+    fn flags() {
+        assert_eq!(format_flags(FLAGS, 0x12, 3), ("SYN|ACK (0x012)".to_string(), true));
+        // The zero mask never matches, no set bits are shown as none
+        assert_eq!(format_flags(FLAGS, 0, 2), ("none (0x00)".to_string(), true));
+        assert_eq!(format_flags(FLAGS, 0x1, 2), ("FIN (0x01)".to_string(), true));
+        assert_eq!(format_flags(FLAGS, 0x1C2, 3), ("SYN|0x1C0 (0x1C2)".to_string(), false));
+        assert_eq!(format_flags(FLAGS, 0x100, 3), ("0x100 (0x100)".to_string(), false));
+        // Tables listing the high bits first, like protocol diagrams, are shown the same way
+        let reversed: NameTable = &[(0x10, "ACK"), (0x2, "SYN"), (0x1, "FIN")];
+        assert_eq!(format_flags(reversed, 0x12, 3), ("SYN|ACK (0x012)".to_string(), true));
+    }
+
+    fn field(offset: usize, len: usize, ty: BitTy) -> BitField {
+        BitField { offset, len, ty, tag: "field".to_string() }
+    }
+
+    #[test]
+    fn bits() {
+        let buf = [0b0101_1010, 0b1100_0011];
+        assert_eq!(read_bits(&buf, 0, 4), 0b0101);
+        assert_eq!(read_bits(&buf, 4, 8), 0b1010_1100);
+        assert_eq!(read_bits(&buf, 0, 16), 0x5AC3);
+        assert_eq!(read_bits(&buf, 15, 1), 1);
+
+        let (diagram, value, known) = format_bits(&buf, &field(4, 8, BitTy::Num));
+        assert_eq!(diagram, "....1010 1100....");
+        assert_eq!((value.as_str(), known), ("172", true));
+
+        // The data offset and flags of a TCP header
+        let (diagram, value, known) = format_bits(&buf, &field(7, 9, BitTy::Flags(FLAGS)));
+        assert_eq!(diagram, ".......0 11000011");
+        assert_eq!((value.as_str(), known), ("FIN|SYN|0xC0 (0x0C3)", false));
+
+        let (_, value, known) = format_bits(&buf, &field(0, 4, BitTy::Enum(&[(5, "five")])));
+        assert_eq!((value.as_str(), known), ("five (0x5)", true));
+        let (_, value, known) = format_bits(&buf, &field(4, 4, BitTy::Enum(&[(5, "five")])));
+        assert_eq!((value.as_str(), known), ("<unknown> (0xA)", false));
+    }
+
+    #[test]
+    fn html_escape() {
+        assert_eq!(HtmlPrinter::<Vec<u8>>::escape("<'&\">"), "&lt;&#39;&amp;&quot;&gt;");
+        assert_eq!(HtmlPrinter::<Vec<u8>>::escape("a<b>é"), "a&lt;b&gt;é");
+
+        let kind = SegmentKind::Line { tag: "<a href='x'>\"".to_string(), paint: Paint::Style(Style::fg(Color::White)) };
+        let line = Segment::new(0x10, Ty::Text(Encoding::Utf8), kind);
+        let attrs = HtmlPrinter::<Vec<u8>>::data_attrs(b"x\"y'", &line);
+        assert_eq!(attrs, " data-range='0x10..0x14' data-ty='text utf8' data-tag='&lt;a href=&#39;x&#39;&gt;&quot;' data-value='x&quot;y&#39;'");
+    }
+
+    #[test]
+    fn html_keeps_lazy_blocks_collapsed() {
+        let buf = (0..16).collect::<Vec<u8>>();
+        let mut printer = HtmlPrinter::new(&buf[..]);
+        {
+            let mut builder = printer.style_builder();
+            for begin in (0..16).step_by(4) {
+                builder.lazy_block(begin, begin + 4, Ty::Binary, block_styler);
+            }
+        }
+        printer.set_marks(vec![5..6, 14..15]);
+        printer.print_main(&buf, &printer.main, 0);
+        assert!(printer.main.childs.values().all(|seg| seg.lazy.as_ref().unwrap().childs.get().is_some()));
+        let collapsed = printer.main.childs.iter().map(|(&(begin, end), seg)| printer.is_collapsed(&buf[begin..end], seg)).collect::<Vec<_>>();
+        assert_eq!(collapsed, vec![true, false, true, false]);
+    }
+
+    #[test]
+    fn cells_of_odd_rows() {
+        let buf = b"H\x00e\x00l\x00l\x00o\x00";
+        let cells = text_cells(&Ty::Text(Encoding::Utf16Le), CharSet::Ascii, buf).unwrap();
+        let rows = cells.chunks(5).map(|row| row.iter().collect::<String>()).collect::<Vec<_>>();
+        assert_eq!(rows, vec!["H·e·l", "·l·o·"]);
+        assert!(text_cells(&Ty::BeNum, CharSet::Ascii, &buf[..2]).is_none());
+    }
+
+    #[test]
+    fn marks() {
+        let marks = merge_marks(vec![9..13, 10..12, 20..22, 2..4, 4..5]);
+        assert_eq!(marks, vec![2..5, 9..13, 20..22]);
+        assert!(is_marked(&marks, 0, 3));
+        assert!(is_marked(&marks, 4, 5));
+        assert!(!is_marked(&marks, 5, 9));
+        assert!(is_marked(&marks, 12, 20));
+        assert!(!is_marked(&marks, 13, 20));
+        assert!(is_marked(&marks, 21, 30));
+        assert!(!is_marked(&marks, 22, 30));
+        assert!(!is_marked(&[], 0, 10));
+    }
+
+    #[test]
+    fn map_keeps_order() {
+        let items = (0..1000).collect::<Vec<_>>();
+        let doubled = (0..1000).map(|i| i * 2).collect::<Vec<_>>();
+        assert_eq!(map_parallel(&items, |&i| i * 2), doubled);
+        assert_eq!(map_chunks(&items, 7, |&i| i * 2), doubled);
+        assert_eq!(map_chunks(&items, 1000, |&i| i * 2), doubled);
+        assert_eq!(map_parallel(&[] as &[usize], |&i| i), vec![]);
+    }
+
+    #[test]
+    fn parallel_styling() {
+        let buf = (0..400).map(|i| i as u8).collect::<Vec<_>>();
+        let ranges = (0..100).map(|i| (i * 4, i * 4 + 4)).collect::<Vec<_>>();
+
+        let mut in_order = Segment::new(0, Ty::Binary, SegmentKind::Main);
+        {
+            let mut builder = StyleBuilder::new(&buf, 0, &mut in_order.childs);
+            for &(begin, end) in &ranges {
+                block_styler(builder.block(begin, end, Ty::Binary));
+            }
+        }
+        let mut parallel = Segment::new(0, Ty::Binary, SegmentKind::Main);
+        StyleBuilder::new(&buf, 0, &mut parallel.childs).blocks(&ranges, Ty::Binary, block_styler);
+        let mut lazy = Segment::new(0, Ty::Binary, SegmentKind::Main);
+        {
+            let mut builder = StyleBuilder::new(&buf, 0, &mut lazy.childs);
+            for &(begin, end) in &ranges {
+                builder.lazy_block(begin, end, Ty::Binary, block_styler);
+            }
+        }
+        let tops = lazy.childs.iter().map(|(&(begin, end), seg)| (&buf[begin..end], seg)).collect::<Vec<_>>();
+        expand_all(&tops);
+        assert!(lazy.childs.values().all(|seg| seg.lazy.as_ref().unwrap().childs.get().is_some()));
+
+        let expected = dump(&buf, &in_order);
+        assert_eq!(expected.len(), 300);
+        assert_eq!(dump(&buf, &parallel), expected);
+        assert_eq!(dump(&buf, &lazy), expected);
     }
 }
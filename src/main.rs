@@ -1,88 +1,320 @@
-extern crate itertools;
 extern crate byteorder;
 extern crate termion;
 extern crate chrono;
+extern crate memmap2;
 
 extern crate hex_view;
 
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::ops::Deref;
+
 use byteorder::{BigEndian, LittleEndian, ByteOrder};
 
 use hex_view::*;
 use hex_view::Color::*;
 
+const BLOCK_TYPES: NameTable = &[
+    (0x0A0D0D0A, "header"),
+    (0x1, "iface descr"),
+    (0x2, "packet"),
+    (0x3, "simple packet"),
+    (0x4, "name resolution"),
+    (0x5, "iface statistics"),
+    (0x6, "enhanced block"),
+];
+
+const IFACE_OPTS: NameTable = &[
+    (0, "end of opts"),
+    (2, "name"),
+    (3, "descr"),
+    (4, "ipv4 addr"),
+    (5, "ipv6 addr"),
+    (9, "tmstamp res"),
+    (12, "OS"),
+];
+
+const ETH_TYPES: NameTable = &[
+    (0x0800, "IPv4"),
+    (0x0806, "ARP"),
+    (0x0842, "Wake-on-LAN"),
+    (0x22F3, "IETF TRILL Protocol"),
+    (0x22EA, "Stream Reservation Protocol"),
+    (0x6003, "DECnet phase IV"),
+    (0x86DD, "IPv6"),
+];
+
+const IP_PROTOS: NameTable = &[
+    (0x06, "TCP"),
+    (0x11, "UDP"),
+];
+
+const TCP_FLAGS: NameTable = &[
+    (0x100, "NS"),
+    (0x80, "CWR"),
+    (0x40, "ECE"),
+    (0x20, "URG"),
+    (0x10, "ACK"),
+    (0x08, "PSH"),
+    (0x04, "RST"),
+    (0x02, "SYN"),
+    (0x01, "FIN"),
+];
+
 fn main() {
-    use std::io::prelude::*;
-    use std::fs::File;
+    let mut path = None;
+    let mut charset = CharSet::Ascii;
+    let mut layout = Layout::default();
+    let mut color_support = None;
+    let mut json = false;
+    let mut filter = Filter::default();
+    let mut offset = None;
+    let mut length = None;
+    let mut find = None;
+    let mut mark = None;
+    let mut diff = None;
+    let mut fields = false;
+    let mut stats = false;
+    let mut strings = None;
+    let mut stream = false;
+    let mut row_len = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "--charset" => {
+                let name = args.next().expect("Missing charset");
+                charset = CharSet::from_name(&name).unwrap_or_else(|| panic!("Unknown charset {}", name));
+            }
+            "--row" => row_len = Some(args.next().and_then(|n| n.parse().ok()).filter(|&n| n != 0).expect("Invalid bytes per row")),
+            "--group" => {
+                layout.group_size = args.next().and_then(|n| n.parse().ok()).expect("Invalid group size");
+            }
+            "--cells" => {
+                let name = args.next().expect("Missing cell format");
+                layout.cell = CellFormat::from_name(&name).unwrap_or_else(|| panic!("Unknown cell format {}", name));
+            }
+            "--colors" => {
+                let name = args.next().expect("Missing color support");
+                color_support = Some(ColorSupport::from_name(&name).unwrap_or_else(|| panic!("Unknown color support {}", name)));
+            }
+            "--json" => json = true,
+            "--lowercase" => layout.uppercase = false,
+            "--aligned" => layout.aligned = true,
+            "--offsets" => {
+                layout.offsets = match &*args.next().expect("Missing offset radix") {
+                    "hex" => Some(Offsets { radix: Radix::Hex, ..layout.offsets.unwrap_or_default() }),
+                    "dec" => Some(Offsets { radix: Radix::Decimal, ..layout.offsets.unwrap_or_default() }),
+                    "none" => None,
+                    radix => panic!("Unknown offset radix {}", radix),
+                };
+            }
+            "--offset-width" => {
+                let width = args.next().and_then(|n| n.parse().ok()).expect("Invalid offset width");
+                layout.offsets.as_mut().expect("Offsets are disabled").width = width;
+            }
+            "--base" => {
+                let base = args.next().as_ref().and_then(|n| parse_num(n)).expect("Invalid base address");
+                layout.offsets.as_mut().expect("Offsets are disabled").base = base;
+            }
+            "--offset" => offset = Some(args.next().as_ref().and_then(|n| parse_num(n)).expect("Invalid offset") as usize),
+            "--length" => length = Some(args.next().as_ref().and_then(|n| parse_num(n)).expect("Invalid length") as usize),
+            "--find" => {
+                let arg = args.next().expect("Missing search pattern");
+                find = Some(Pattern::from_arg(&arg).unwrap_or_else(|| panic!("Invalid search pattern {}", arg)));
+            }
+            "--mark" => {
+                let arg = args.next().expect("Missing search pattern");
+                mark = Some(Pattern::from_arg(&arg).unwrap_or_else(|| panic!("Invalid search pattern {}", arg)));
+            }
+            "--diff" => diff = Some(args.next().expect("Missing file to compare with")),
+            "--fields" => fields = true,
+            "--stats" => stats = true,
+            "--stream" => stream = true,
+            "--strings" => strings = Some(args.next().and_then(|n| n.parse().ok()).expect("Invalid min string len")),
+            "--select" => {
+                let arg = args.next().expect("Missing selection");
+                filter.select.push(Select::from_arg(&arg).unwrap_or_else(|| panic!("Invalid selection {}", arg)));
+            }
+            _ => path = Some(arg),
+        }
+    }
 
-    let mut buf = Vec::new();
-    let mut file = File::open(std::env::args()
-                                  .skip(1)
-                                  .next()
-                                  .expect("No file to view"))
-            .expect("File not found");
-    file.read_to_end(&mut buf).unwrap();
-
-    let mut term_printer = TermPrinter::new(buf.clone());
-    pcapng_styler(term_printer.style_builder());
-    term_printer.print();
+    // Diffs show two rows next to each other
+    layout.bytes_per_row = row_len.unwrap_or(if diff.is_some() { 16 } else { layout.bytes_per_row });
+
+    if offset.is_some() || length.is_some() {
+        let start = offset.unwrap_or(0);
+        filter.range = Some(start..length.map_or(usize::MAX, |length| start.saturating_add(length)));
+    }
+
+    let path = path.expect("No file to view");
+    let file = File::open(&path).expect("File not found");
+    // Streaming is only done when asked for, as the streamed page has no outline and no heatmap.
+    // The whole file is needed when aligning rows across blocks, for the JSON tree, for
+    // searching and for finding strings in files which aren't pcapng. Pipes can only be read
+    // once, but the terminal and the HTML view each read the file when streaming.
+    let stream = stream
+        && !(json || layout.aligned || find.is_some() || mark.is_some() || diff.is_some() || strings.is_some())
+        && file.metadata().is_ok_and(|metadata| metadata.is_file());
+    let whole = if stream { None } else { Some(load(&file)) };
+    let buf: &[u8] = whole.as_ref().map(|whole| &whole[..]).unwrap_or(&[]);
+    let styler = |builder: StyleBuilder| match strings {
+        Some(min_len) => strings::style_strings(builder, min_len),
+        None => pcapng_styler(builder),
+    };
+
+    if let Some(other) = diff {
+        let other = File::open(&other).expect("File not found");
+        let other = load(&other);
+        let mut diff_printer = DiffPrinter::new(buf, &other[..]);
+        diff_printer.set_charset(charset);
+        diff_printer.set_layout(layout);
+        if let Some(color_support) = color_support {
+            diff_printer.set_color_support(color_support);
+        }
+        diff_printer.style(styler);
+        match (fields, json) {
+            (true, true) => json::print_changes(&diff_printer.changes()),
+            (true, false) => diff::print_changes(&diff_printer.changes()),
+            (false, _) => diff_printer.print(),
+        }
+        return;
+    }
+
+    if json {
+        let mut json_printer = JsonPrinter::new(buf);
+        json_printer.set_filter(filter);
+        styler(json_printer.style_builder());
+        json_printer.print();
+        return;
+    }
 
+    if let Some(pattern) = find {
+        let mut printer = TermPrinter::new(buf);
+        styler(printer.style_builder());
+        for found in search::search(printer.root(), &pattern) {
+            let seg = found.seg;
+            let name = match *seg.kind() {
+                SegmentKind::Main => "file",
+                SegmentKind::Header => "header",
+                SegmentKind::Block => "block",
+                SegmentKind::Line { ref tag, .. } => tag,
+            };
+            match seg.value() {
+                Some(value) => println!("0x{:08X}..0x{:08X}  {}: {}", found.range.start, found.range.end, name, value),
+                None => println!("0x{:08X}..0x{:08X}  {}", found.range.start, found.range.end, name),
+            }
+        }
+        return;
+    }
+
+    if stream {
+        let mut term_stream = TermStream::new(layout.clone(), Theme::default(), filter.clone());
+        term_stream.set_charset(charset);
+        if let Some(color_support) = color_support {
+            term_stream.set_color_support(color_support);
+        }
+        term_stream.set_stats(stats);
+        term_stream.print(BufReader::new(file), 8, pcapng_block_len, pcapng_styler);
+        let mut html_stream = HtmlStream::new(layout, Theme::default(), filter);
+        html_stream.set_charset(charset);
+        html_stream.set_stats(stats);
+        let file = File::open(&path).expect("File not found");
+        html_stream.print(BufReader::new(file), 8, pcapng_block_len, pcapng_styler);
+        return;
+    }
+
+    let mut term_printer = TermPrinter::new(buf);
     let mut html_printer = HtmlPrinter::new(buf);
-    pcapng_styler(html_printer.style_builder());
+
+    term_printer.set_charset(charset);
+    term_printer.set_layout(layout.clone());
+    if let Some(color_support) = color_support {
+        term_printer.set_color_support(color_support);
+    }
+    html_printer.set_charset(charset);
+    html_printer.set_layout(layout);
+    term_printer.set_stats(stats);
+    html_printer.set_stats(if stats { Some(256) } else { None });
+    term_printer.set_filter(filter.clone());
+    html_printer.set_filter(filter);
+
+    styler(term_printer.style_builder());
+    styler(html_printer.style_builder());
+    if let Some(pattern) = mark {
+        let marks = search::search(term_printer.root(), &pattern).into_iter().map(|found| found.range).collect::<Vec<_>>();
+        term_printer.set_marks(marks.clone());
+        html_printer.set_marks(marks);
+    }
+    term_printer.print();
     html_printer.print();
 }
 
+/// Maps regular files into memory. Other files like pipes can't be mapped and are read instead.
+fn load(mut file: &File) -> Box<dyn Deref<Target = [u8]>> {
+    if file.metadata().is_ok_and(|metadata| metadata.is_file()) {
+        // SAFETY: The mapped bytes are only valid while no other process truncates or writes to
+        // the file. Like other viewers of large files we assume that files aren't changed while
+        // they are viewed.
+        return Box::new(unsafe { memmap2::Mmap::map(file) }.expect("Failed to map file"));
+    }
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf).expect("Failed to read file");
+    Box::new(buf)
+}
+
+/// Parses a decimal or `0x` prefixed hex number
+fn parse_num(num: &str) -> Option<u64> {
+    match num.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => num.parse().ok(),
+    }
+}
+
+/// Length of a block from its first 8 bytes, including the padding to 4 bytes
+fn pcapng_block_len(head: &[u8]) -> usize {
+    let len = LittleEndian::read_u32(&head[4..8]);
+    let len = len +
+              match len % 4 {
+                  0 => 0,
+                  1 => 3,
+                  2 => 2,
+                  3 => 1,
+                  _ => unreachable!(),
+              };
+    len as usize
+}
+
+/// Styles whole blocks. The first block of the file is the header.
 fn pcapng_styler(mut builder: StyleBuilder) {
-    let header_len = LittleEndian::read_u32(&builder.buf[4..8]);
-    let header_len = header_len +
-                     match header_len % 4 {
-                         0 => 0,
-                         1 => 3,
-                         2 => 2,
-                         3 => 1,
-                         _ => unreachable!(),
-                     };
-    //println!("Header len: {}", header_len);
-    pcapng_block_styler(builder.header(0, header_len as usize, Ty::Ascii));
-
-    let mut begin = header_len as usize;
-    loop {
-        let len = LittleEndian::read_u32(&builder.buf[begin + 4..begin + 8]);
-        let len = len +
-                  match len % 4 {
-                      0 => 0,
-                      1 => 3,
-                      2 => 2,
-                      3 => 1,
-                      _ => unreachable!(),
-                  };
-        //println!("Len: {}", len);
-        pcapng_block_styler(builder.block(begin, begin + len as usize, Ty::Ascii));
-
-        begin += len as usize;
-        if begin >= builder.buf.len() {
-            break;
+    let mut begin = 0;
+    if builder.offset() == 0 {
+        begin = pcapng_block_len(builder.buf);
+        pcapng_block_styler(builder.header(0, begin, Ty::Ascii));
+    }
+
+    // Enhanced packet blocks are only styled when they are needed, the printers style them in
+    // parallel
+    let mut ranges = Vec::new();
+    while begin < builder.buf.len() {
+        let len = pcapng_block_len(&builder.buf[begin..]);
+        if LittleEndian::read_u32(&builder.buf[begin..begin + 4]) == 0x6 {
+            builder.lazy_block(begin, begin + len, Ty::Ascii, pcapng_block_styler);
+        } else {
+            ranges.push((begin, begin + len));
         }
+        begin += len;
     }
+    builder.blocks(&ranges, Ty::Ascii, pcapng_block_styler);
 }
 
 fn pcapng_block_styler(mut builder: StyleBuilder) {
-    use chrono::offset::TimeZone;
-
     let buf = builder.buf;
 
-    let custom = Ty::custom;
     let type_id = LittleEndian::read_u32(&buf[0..4]);
-    let type_ty = match type_id {
-        0x0A0D0D0A => custom("header"),
-        0x1 => custom("iface descr"),
-        0x2 => custom("packet"),
-        0x3 => custom("simple packet"),
-        0x4 => custom("name resolution"),
-        0x5 => custom("iface statistics"),
-        0x6 => custom("enhanced block"),
-        _ => Ty::LeNum,
-    };
-    builder.line(4, type_ty, "type");
+    builder.set_role(Role::Header);
+    builder.line(4, Ty::Enum(Endian::Little, BLOCK_TYPES), "type");
+    builder.set_role(Role::Length);
     builder.line(4, Ty::LeNum, "size");
 
 
@@ -96,24 +328,10 @@ fn pcapng_block_styler(mut builder: StyleBuilder) {
             builder.line(4, Ty::LeNum, "snap num");
             builder.set_color(Yellow);
 
-            let mut offset = 16 as usize;
+            let mut offset = 16usize;
             for i in 0.. {
                 let opt1_type_num = LittleEndian::read_u16(&buf[offset..offset + 2]);
-                let opt1_type = match opt1_type_num {
-                    0 => "end of opts",
-                    2 => "name",
-                    3 => "descr",
-                    4 => "ipv4 addr",
-                    5 => "ipv6 addr",
-                    9 => "tmstamp res",
-                    12 => "OS",
-                    _ => {
-                        builder.set_color(Red);
-                        "<unknown>"
-                    },
-                };
-                builder.line(2, Ty::custom(opt1_type), format!("opt{} type", i));
-                builder.set_color(Yellow);
+                builder.line(2, Ty::Enum(Endian::Little, IFACE_OPTS), format!("opt{} type", i));
 
                 let opt1_len = LittleEndian::read_u16(&buf[offset + 2..offset + 4]) as usize;
                 let opt1_len = opt1_len +
@@ -135,7 +353,12 @@ fn pcapng_block_styler(mut builder: StyleBuilder) {
                 };
                 assert_eq!(opt1_len, opt1_len_adapted, "Opt len is wrong");
                 builder.line(2, Ty::LeNum, format!("opt{}  len", i));
-                builder.line(opt1_len, Ty::Ascii, format!("opt{} data", i));
+                match opt1_type_num {
+                    2 | 3 | 12 => {
+                        builder.string(StrLen::Fixed(opt1_len), Encoding::Utf8, format!("opt{} data", i));
+                    }
+                    _ => builder.line(opt1_len, Ty::Ascii, format!("opt{} data", i)),
+                }
                 offset += 4 + opt1_len;
                 if offset >= buf.len() - 4 {
                     break;
@@ -147,8 +370,6 @@ fn pcapng_block_styler(mut builder: StyleBuilder) {
         0x6 => {
             builder.set_color(Magenta);
             builder.line(4, Ty::LeNum, "iface id");
-            let timestamp = LittleEndian::read_u64(&buf[12..20]);
-            println!("{}", timestamp);
             builder.line(8,
                          Ty::Custom(/*chrono::Utc.timestamp(timestamp as i64, 0)).to_rfc2822()*/
                                     "".to_string()),
@@ -158,38 +379,35 @@ fn pcapng_block_styler(mut builder: StyleBuilder) {
             builder.line(6, Ty::Binary, "dest mac");
             builder.line(6, Ty::Binary, "src mac");
             let eth_type_num = BigEndian::read_u16(&buf[40..42]);
-            let eth_type = match eth_type_num {
-                0x0800 => "IPv4",
-                0x0806 => "ARP",
-                0x0842 => "Wake-on-LAN",
-                0x22F3 => "IETF TRILL Protocol",
-                0x22EA => "Stream Reservation Protocol",
-                0x6003 => "DECnet phase IV",
-                0x86DD => "IPv6",
-                _ => {
-                    builder.set_color(Red);
-                    "<unknown>"
-                },
-            };
-            builder.line(2, Ty::custom(eth_type), "eth type");
-            builder.set_color(Magenta);
+            builder.line(2, Ty::Enum(Endian::Big, ETH_TYPES), "eth type");
 
             if eth_type_num == 0x0800 {
                 builder.set_color(Cyan);
-                builder.line(1, Ty::Binary, "version + IHL");
-                builder.line(1, Ty::Binary, "DSCP + ECN");
+                {
+                    let mut bits = builder.bitfield(1, Ty::Binary, "version + IHL");
+                    bits.field(4, BitTy::Num, "version");
+                    bits.field(4, BitTy::Num, "IHL");
+                }
+                {
+                    let mut bits = builder.bitfield(1, Ty::Binary, "DSCP + ECN");
+                    bits.field(6, BitTy::Num, "DSCP");
+                    bits.field(2, BitTy::Enum(&[(0, "Not-ECT"), (1, "ECT(1)"), (2, "ECT(0)"), (3, "CE")]), "ECN");
+                }
                 builder.line(2, Ty::BeNum, "total length");
                 builder.line(2, Ty::Binary, "identification");
-                builder.line(2, Ty::Binary, "flags + frag offset");
+                {
+                    let mut bits = builder.bitfield(2, Ty::Binary, "flags + frag offset");
+                    bits.field(1, BitTy::Num, "reserved");
+                    bits.field(1, BitTy::Num, "DF");
+                    bits.field(1, BitTy::Num, "MF");
+                    bits.field(13, BitTy::Num, "frag offset");
+                }
                 builder.line(1, Ty::BeNum, "TTL");
                 let proto_num = buf[51];
-                let proto = match proto_num {
-                    0x06 => "TCP",
-                    0x11 => "UDP",
-                    _ => "<unknown>",
-                };
-                builder.line(1, Ty::custom(proto), "Proto");
+                builder.line(1, Ty::Enum(Endian::Big, IP_PROTOS), "Proto");
+                builder.set_role(Role::Checksum);
                 builder.line(2, Ty::Binary, "Header Checksum");
+                builder.set_color(Cyan);
                 builder.line(4, Ty::Ip4, "src IP");
                 builder.line(4, Ty::Ip4, "dst IP");
                 match proto_num {
@@ -199,12 +417,19 @@ fn pcapng_block_styler(mut builder: StyleBuilder) {
                         builder.line(2, Ty::BeNum, "dst port");
                         builder.line(4, Ty::BeNum, "seq num");
                         builder.line(4, Ty::BeNum, "ack num");
-                        builder.line(4, Ty::Binary, "data offset + opts");
-                        builder.line(4, Ty::BeNum, "window size");
+                        {
+                            let mut bits = builder.bitfield(2, Ty::Binary, "data offset + flags");
+                            bits.field(4, BitTy::Num, "data offset");
+                            bits.field(3, BitTy::Num, "reserved");
+                            bits.field(9, BitTy::Flags(TCP_FLAGS), "flags");
+                        }
+                        builder.line(2, Ty::BeNum, "window size");
+                        builder.set_role(Role::Checksum);
                         builder.line(2, Ty::Binary, "checksum");
+                        builder.set_color(Yellow);
                         builder.line(2, Ty::Binary, "urgent ptr");
 
-                        builder.set_color(White);
+                        builder.set_role(Role::Payload);
                         builder.line_until(buf.len() - 4, Ty::Ascii, "content");
                     }
                     _ => {
@@ -220,6 +445,7 @@ fn pcapng_block_styler(mut builder: StyleBuilder) {
         _ => builder.line_until(buf.len() - 4, Ty::Ascii, "content"),
     }
 
+    builder.set_role(Role::Length);
     builder.line(4, Ty::LeNum, "size");
 }
 
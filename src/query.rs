@@ -0,0 +1,161 @@
+//! Read-only access to the segment tree after styling, for tests and tools which want to
+//! inspect the dissection instead of printing it.
+
+use std::ops::Range;
+
+use {format_bits, format_value, is_unknown_value};
+use {Segment, SegmentKind, Ty};
+
+/// A segment together with its bytes
+#[derive(Copy, Clone)]
+pub struct SegmentRef<'a> {
+    buf: &'a [u8],
+    seg: &'a Segment,
+}
+
+impl<'a> SegmentRef<'a> {
+    pub(crate) fn new(buf: &'a [u8], seg: &'a Segment) -> SegmentRef<'a> {
+        SegmentRef { buf, seg }
+    }
+
+    /// Absolute offset of the first byte
+    pub fn start(&self) -> usize {
+        self.seg.start
+    }
+
+    /// Absolute offset after the last byte
+    pub fn end(&self) -> usize {
+        self.seg.start + self.buf.len()
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.start()..self.end()
+    }
+
+    pub fn bytes(&self) -> &'a [u8] {
+        self.buf
+    }
+
+    pub fn kind(&self) -> &'a SegmentKind {
+        &self.seg.kind
+    }
+
+    pub fn ty(&self) -> &'a Ty {
+        &self.seg.ty
+    }
+
+    /// Tag of a line
+    pub fn tag(&self) -> Option<&'a str> {
+        match self.seg.kind {
+            SegmentKind::Line { ref tag, .. } => Some(tag),
+            _ => None,
+        }
+    }
+
+    /// Decoded value as printed, if the type has one
+    pub fn value(&self) -> Option<String> {
+        format_value(&self.seg.ty, self.buf)
+    }
+
+    /// False for enum values and flags missing in their name table
+    pub fn is_known(&self) -> bool {
+        !is_unknown_value(&self.seg.ty, self.buf)
+    }
+
+    /// Tags and formatted values of the bit fields of a line
+    pub fn bits(&self) -> Vec<(&'a str, String)> {
+        self.seg.bits.iter().map(|field| (&field.tag as &str, format_bits(self.buf, field).1)).collect()
+    }
+
+    /// Direct children ordered by offset. Lazy blocks are styled on first access.
+    pub fn childs(&self) -> impl DoubleEndedIterator<Item = SegmentRef<'a>> {
+        let buf = self.buf;
+        self.seg.expand(buf).iter().map(move |(&(begin, end), child)| SegmentRef::new(&buf[begin..end], child))
+    }
+
+    /// All descendants in depth-first order, parents before their children
+    pub fn iter(&self) -> Iter<'a> {
+        Iter { stack: self.childs().rev().collect() }
+    }
+
+    /// Innermost segment containing the absolute offset `offset`
+    pub fn find(&self, offset: usize) -> Option<SegmentRef<'a>> {
+        if !self.range().contains(&offset) {
+            return None;
+        }
+        let rel = offset - self.start();
+        let child = self.seg.expand(self.buf).range(..=(rel, usize::MAX)).next_back().filter(|&(&(_, end), _)| end > rel);
+        match child {
+            Some((&(begin, end), child)) => SegmentRef::new(&self.buf[begin..end], child).find(offset),
+            None => Some(*self),
+        }
+    }
+
+    /// Looks up a descendant by a path like `block[3]/src IP`. Components are separated by `/`
+    /// and are either `header`, `block` or the tag of a line, optionally followed by the index
+    /// among the siblings matching it. The index defaults to 0.
+    pub fn lookup(&self, path: &str) -> Option<SegmentRef<'a>> {
+        let mut seg = *self;
+        for component in path.split('/') {
+            let (name, index) = match component.rfind('[') {
+                Some(open) if component.ends_with(']') => {
+                    let index = component[open + 1..component.len() - 1].parse().ok()?;
+                    (&component[..open], index)
+                }
+                _ => (component, 0),
+            };
+            seg = seg.childs().filter(|child| child.matches(name)).nth(index)?;
+        }
+        Some(seg)
+    }
+
+    /// `header`, `block` or the tag of a line, as used in paths. Empty for the root.
+    pub fn name(&self) -> &'a str {
+        match self.seg.kind {
+            SegmentKind::Header => "header",
+            SegmentKind::Block => "block",
+            SegmentKind::Line { ref tag, .. } => tag,
+            SegmentKind::Main => "",
+        }
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        !name.is_empty() && self.name() == name
+    }
+}
+
+pub struct Iter<'a> {
+    stack: Vec<SegmentRef<'a>>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = SegmentRef<'a>;
+
+    fn next(&mut self) -> Option<SegmentRef<'a>> {
+        let seg = self.stack.pop()?;
+        self.stack.extend(seg.childs().rev());
+        Some(seg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tests::tree;
+
+    #[test]
+    fn lookup() {
+        let buf = [0, 1, 0, 2, 0, 3, 0, 4, 0, 5];
+        // A header of two bytes and two blocks of two lines each
+        let main = tree(&buf, 2, 4);
+        let root = super::SegmentRef::new(&buf, &main);
+        let value = |path| root.lookup(path).and_then(|seg| seg.value());
+        assert_eq!(value("header/magic").as_deref(), Some("1"));
+        assert_eq!(value("block/port").as_deref(), Some("2"));
+        assert_eq!(value("block[1]/port[1]").as_deref(), Some("5"));
+        assert_eq!(value("block[0]/port[1]").as_deref(), Some("3"));
+        assert!(root.lookup("block[2]").is_none());
+        assert!(root.lookup("block[x]/port").is_none());
+        assert!(root.lookup("block/").is_none());
+        assert_eq!(root.lookup("block[1]").map(|seg| seg.start()), Some(6));
+    }
+}
@@ -0,0 +1,215 @@
+//! Search over the bytes and the decoded values of the segment tree.
+
+use std::cmp::Ordering;
+use std::ops::Range;
+
+use regex::bytes::Regex;
+
+use {Encoding, SegmentRef};
+
+pub enum Pattern {
+    /// Bytes where `None` matches any byte
+    Hex(Vec<Option<u8>>),
+    /// Text in any of the encodings
+    Text(String, Vec<Encoding>),
+    Regex(Regex),
+    /// Lines with the tag whose decoded value compares to the value
+    Value(String, Op, String),
+}
+
+#[derive(Copy, Clone)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+const OPS: &[(&str, Op)] = &[
+    ("==", Op::Eq),
+    ("!=", Op::Ne),
+    ("<=", Op::Le),
+    (">=", Op::Ge),
+    ("<", Op::Lt),
+    (">", Op::Gt),
+];
+
+const ENCODINGS: &[Encoding] = &[Encoding::Utf8, Encoding::Utf16Le, Encoding::Utf16Be, Encoding::Latin1, Encoding::Ebcdic];
+
+impl Pattern {
+    /// Parses `hex:DE AD ?? EF`, `text:GET` for all encodings, `utf16le:GET` for a single
+    /// encoding, `re:HTTP/1\.[01]` and value queries like `dst port == 443`. Regexes match bytes,
+    /// so `\xFF` matches the byte 0xFF and `.` any byte.
+    pub fn from_arg(arg: &str) -> Option<Pattern> {
+        if let Some(hex) = arg.strip_prefix("hex:") {
+            let digits = hex.chars().filter(|c| !c.is_whitespace()).collect::<Vec<_>>();
+            if digits.is_empty() || digits.len() % 2 != 0 {
+                return None;
+            }
+            let bytes = digits.chunks(2).map(|pair| match *pair {
+                ['?', '?'] => Some(None),
+                [hi, lo] => Some(Some((hi.to_digit(16)? << 4 | lo.to_digit(16)?) as u8)),
+                _ => unreachable!(),
+            });
+            return bytes.collect::<Option<_>>().map(Pattern::Hex);
+        }
+        if let Some(text) = arg.strip_prefix("text:") {
+            return Some(Pattern::Text(text.to_string(), ENCODINGS.to_vec()));
+        }
+        if let Some(re) = arg.strip_prefix("re:") {
+            return Regex::new(&format!("(?s-u){}", re)).ok().map(Pattern::Regex);
+        }
+        if let Some(colon) = arg.find(':') {
+            if let Some(encoding) = Encoding::from_name(&arg[..colon]) {
+                return Some(Pattern::Text(arg[colon + 1..].to_string(), vec![encoding]));
+            }
+        }
+        let &(op_text, op) = OPS.iter().filter(|&&(op_text, _)| arg.contains(op_text)).min_by_key(|&&(op_text, _)| arg.find(op_text))?;
+        let at = arg.find(op_text)?;
+        Some(Pattern::Value(arg[..at].trim().to_string(), op, arg[at + op_text.len()..].trim().to_string()))
+    }
+}
+
+/// Whether the formatted value `found` is `value`. Named values like `IPv6 (0x86DD)` also match
+/// by their name alone.
+pub(crate) fn value_is(found: &str, value: &str) -> bool {
+    found == value || found.starts_with(&format!("{} (", value))
+}
+
+fn compare(found: &str, op: Op, value: &str) -> bool {
+    let ordering = match (found.parse::<i128>(), value.parse::<i128>()) {
+        (Ok(found), Ok(value)) => found.cmp(&value),
+        _ => match op {
+            Op::Eq => return value_is(found, value),
+            Op::Ne => return !value_is(found, value),
+            _ => return false,
+        },
+    };
+    match op {
+        Op::Eq => ordering == Ordering::Equal,
+        Op::Ne => ordering != Ordering::Equal,
+        Op::Lt => ordering == Ordering::Less,
+        Op::Le => ordering != Ordering::Greater,
+        Op::Gt => ordering == Ordering::Greater,
+        Op::Ge => ordering != Ordering::Less,
+    }
+}
+
+pub struct Match<'a> {
+    /// Absolute range of the matching bytes
+    pub range: Range<usize>,
+    /// The matching line for value queries, else the innermost segment containing the first
+    /// matching byte
+    pub seg: SegmentRef<'a>,
+}
+
+/// Starts of the occurrences of `needle` in `buf`, which may overlap
+fn find_bytes(buf: &[u8], needle: &[Option<u8>]) -> Vec<usize> {
+    if needle.is_empty() || needle.len() > buf.len() {
+        return Vec::new();
+    }
+    buf.windows(needle.len())
+        .enumerate()
+        .filter(|&(_, window)| window.iter().zip(needle).all(|(&b, n)| n.is_none_or(|n| n == b)))
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// All matches below `root` ordered by offset
+pub fn search<'a>(root: SegmentRef<'a>, pattern: &Pattern) -> Vec<Match<'a>> {
+    let buf = root.bytes();
+    let mut ranges = match *pattern {
+        Pattern::Hex(ref needle) => find_bytes(buf, needle).into_iter().map(|i| i..i + needle.len()).collect(),
+        Pattern::Text(ref text, ref encodings) => {
+            let mut ranges = Vec::new();
+            for encoded in encodings.iter().filter_map(|encoding| encoding.encode(text)) {
+                let needle = encoded.iter().map(|&b| Some(b)).collect::<Vec<_>>();
+                ranges.extend(find_bytes(buf, &needle).into_iter().map(|i| i..i + needle.len()));
+            }
+            ranges
+        }
+        Pattern::Regex(ref re) => re.find_iter(buf).filter(|m| !m.range().is_empty()).map(|m| m.range()).collect::<Vec<_>>(),
+        Pattern::Value(ref tag, op, ref value) => {
+            return root
+                .iter()
+                .filter(|seg| seg.tag() == Some(tag))
+                .filter(|seg| seg.value().is_some_and(|found| compare(&found, op, value)))
+                .map(|seg| Match { range: seg.range(), seg })
+                .collect();
+        }
+    };
+    // Encodings agreeing on the text find the same bytes
+    ranges.sort_by_key(|range: &Range<usize>| (range.start, range.end));
+    ranges.dedup();
+    ranges
+        .into_iter()
+        .map(|range| {
+            let range = root.start() + range.start..root.start() + range.end;
+            let seg = root.find(range.start).expect("Match outside of the root");
+            Match { range, seg }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(arg: &str) -> Option<Vec<Option<u8>>> {
+        match Pattern::from_arg(arg) {
+            Some(Pattern::Hex(bytes)) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn hex_patterns() {
+        assert_eq!(hex("hex:DE AD ?? ef"), Some(vec![Some(0xDE), Some(0xAD), None, Some(0xEF)]));
+        assert_eq!(hex("hex:DEA"), None);
+        assert_eq!(hex("hex:DG"), None);
+        assert_eq!(hex("hex:"), None);
+    }
+
+    #[test]
+    fn text_patterns() {
+        match Pattern::from_arg("text:GET") {
+            Some(Pattern::Text(ref text, ref encodings)) => assert!(text == "GET" && encodings.len() == ENCODINGS.len()),
+            _ => panic!("Not a text pattern"),
+        }
+        match Pattern::from_arg("utf16le:a:b") {
+            Some(Pattern::Text(ref text, ref encodings)) => assert!(text == "a:b" && encodings.len() == 1),
+            _ => panic!("Not a text pattern"),
+        }
+    }
+
+    #[test]
+    fn regex_patterns() {
+        match Pattern::from_arg(r"re:\xFF.") {
+            Some(Pattern::Regex(ref re)) => assert!(re.is_match(b"\xFF\n") && !re.is_match(b"\xFE\n")),
+            _ => panic!("Not a regex pattern"),
+        }
+        assert!(Pattern::from_arg("re:(").is_none());
+    }
+
+    #[test]
+    fn value_patterns() {
+        let value = |arg| match Pattern::from_arg(arg) {
+            Some(Pattern::Value(tag, op, value)) => Some((tag, op as u8, value)),
+            _ => None,
+        };
+        assert_eq!(value("dst port == 443"), Some(("dst port".to_string(), Op::Eq as u8, "443".to_string())));
+        assert_eq!(value("len<=10"), Some(("len".to_string(), Op::Le as u8, "10".to_string())));
+        assert_eq!(value("type != IPv6"), Some(("type".to_string(), Op::Ne as u8, "IPv6".to_string())));
+        assert_eq!(value("nothing"), None);
+    }
+
+    #[test]
+    fn compare_values() {
+        assert!(compare("443", Op::Eq, "443"));
+        assert!(compare("-2", Op::Lt, "1"));
+        assert!(compare("IPv6 (0x86DD)", Op::Eq, "IPv6"));
+        assert!(!compare("IPv6 (0x86DD)", Op::Gt, "IPv6"));
+    }
+}
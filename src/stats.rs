@@ -0,0 +1,93 @@
+//! Byte statistics to locate compressed or encrypted regions in unknown data.
+
+pub struct Stats {
+    pub len: usize,
+    /// Number of occurrences of every byte value
+    pub histogram: [usize; 256],
+}
+
+impl Stats {
+    pub fn new(buf: &[u8]) -> Stats {
+        let mut stats = Stats { len: 0, histogram: [0; 256] };
+        stats.add(buf);
+        stats
+    }
+
+    /// Adds the bytes of `buf`, for data which isn't in memory at once
+    pub fn add(&mut self, buf: &[u8]) {
+        for &b in buf {
+            self.histogram[b as usize] += 1;
+        }
+        self.len += buf.len();
+    }
+
+    /// Shannon entropy in bits per byte, from 0 for a single repeated byte to 8 for random data
+    pub fn entropy(&self) -> f64 {
+        let len = self.len as f64;
+        // Summing floats starts at -0.0, which would be shown as -0.00
+        self.histogram.iter().filter(|&&count| count != 0).fold(0.0, |entropy, &count| {
+            let p = count as f64 / len;
+            entropy - p * p.log2()
+        })
+    }
+
+    /// Share of printable ASCII chars, including whitespace
+    pub fn printable(&self) -> f64 {
+        if self.len == 0 {
+            return 0.0;
+        }
+        let printable = (0..=255u8).filter(|b| b.is_ascii_graphic() || b" \t\n\r".contains(b));
+        printable.map(|b| self.histogram[b as usize]).sum::<usize>() as f64 / self.len as f64
+    }
+
+    /// The `n` most common byte values with their counts, most common first
+    pub fn most_common(&self, n: usize) -> Vec<(u8, usize)> {
+        let mut common = (0..256).filter(|&b| self.histogram[b] != 0).map(|b| (b as u8, self.histogram[b])).collect::<Vec<_>>();
+        common.sort_by_key(|&(b, count)| (usize::MAX - count, b));
+        common.truncate(n);
+        common
+    }
+
+    /// Like `entropy 7.92, 38% printable, common 00 (12%) FF (3%)`
+    pub fn summary(&self) -> String {
+        let common = self.most_common(3).iter().map(|&(b, count)| format!(" {:02X} ({:.0}%)", b, count as f64 * 100.0 / self.len as f64)).collect::<String>();
+        format!("entropy {:.2}, {:.0}% printable, common{}", self.entropy(), self.printable() * 100.0, common)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty() {
+        let stats = Stats::new(&[]);
+        assert_eq!(stats.entropy(), 0.0);
+        assert_eq!(stats.printable(), 0.0);
+        assert!(stats.most_common(3).is_empty());
+        assert_eq!(stats.summary(), "entropy 0.00, 0% printable, common");
+    }
+
+    #[test]
+    fn entropy() {
+        assert_eq!(Stats::new(&[0xAA; 100]).summary(), "entropy 0.00, 0% printable, common AA (100%)");
+        let all = (0..=255u8).cycle().take(256 * 4).collect::<Vec<_>>();
+        assert!((Stats::new(&all).entropy() - 8.0).abs() < 1e-9);
+        assert!((Stats::new(b"abab").entropy() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn printable() {
+        assert_eq!(Stats::new(b"ab \n\x00\xFF\x01\x7F").printable(), 0.5);
+    }
+
+    #[test]
+    fn most_common() {
+        let mut stats = Stats::new(b"aabbbc");
+        stats.add(b"\x00");
+        assert_eq!(stats.len, 7);
+        assert_eq!(stats.most_common(2), vec![(b'b', 3), (b'a', 2)]);
+        // Ties are ordered by byte value
+        assert_eq!(stats.most_common(5), vec![(b'b', 3), (b'a', 2), (0, 1), (b'c', 1)]);
+    }
+}
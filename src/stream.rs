@@ -0,0 +1,242 @@
+//! Reading of top-level blocks a batch at a time, so files larger than memory can be printed.
+
+use std::io::{self, Read};
+
+use {CharSet, ColorSupport, Filter, HtmlPrinter, Layout, Segment, SegmentKind, Stats, StyleBuilder, TermPrinter, Theme, Ty};
+
+/// Bytes of blocks styled and printed at once, so that their lazy blocks can be styled in
+/// parallel
+const BATCH_LEN: usize = 1 << 20;
+
+/// Reads as many bytes as fit in `buf`, less only at the end of the input
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(read)
+}
+
+/// Calls `f` with the absolute offset and the bytes of batches of whole blocks, each ending with
+/// the first block which reaches `batch_len` bytes. `block_len` gets the first `head_len` bytes
+/// of a block and returns its total length. Only one batch is kept in memory.
+fn for_each_batch<R, L, F>(mut reader: R, head_len: usize, batch_len: usize, mut block_len: L, mut f: F)
+where
+    R: Read,
+    L: FnMut(&[u8]) -> usize,
+    F: FnMut(usize, &[u8]),
+{
+    let mut offset = 0;
+    let mut buf = Vec::new();
+    loop {
+        let start = buf.len();
+        buf.resize(start + head_len, 0);
+        let read = read_full(&mut reader, &mut buf[start..]).expect("Failed to read block");
+        if read == 0 {
+            buf.truncate(start);
+            break;
+        }
+        assert!(read == head_len, "Truncated block head at offset {}", offset + start);
+        let len = block_len(&buf[start..]);
+        assert!(len >= head_len, "Block len {} at offset {} is shorter than its head", len, offset + start);
+        buf.resize(start + len, 0);
+        reader.read_exact(&mut buf[start + head_len..]).unwrap_or_else(|err| panic!("Truncated block at offset {}: {}", offset + start, err));
+        if buf.len() >= batch_len {
+            f(offset, &buf);
+            offset += buf.len();
+            buf.clear();
+        }
+    }
+    if !buf.is_empty() {
+        f(offset, &buf);
+    }
+}
+
+/// Prints blocks to the terminal as soon as they are styled, for files larger than memory.
+/// Aligned rows are not supported.
+pub struct TermStream {
+    printer: TermPrinter<&'static [u8]>,
+    batch_len: usize,
+}
+
+impl TermStream {
+    pub fn new(layout: Layout, theme: Theme, filter: Filter) -> TermStream {
+        assert!(!layout.aligned, "Aligned rows can't be streamed");
+        let mut printer = TermPrinter::new(&[][..]);
+        printer.set_layout(layout);
+        printer.set_theme(theme);
+        printer.set_filter(filter);
+        TermStream { printer, batch_len: BATCH_LEN }
+    }
+
+    /// See `TermPrinter::set_charset`
+    pub fn set_charset(&mut self, charset: CharSet) {
+        self.printer.set_charset(charset);
+    }
+
+    /// See `TermPrinter::set_color_support`
+    pub fn set_color_support(&mut self, color_support: ColorSupport) {
+        self.printer.set_color_support(color_support);
+    }
+
+    /// See `TermPrinter::set_stats`
+    pub fn set_stats(&mut self, stats: bool) {
+        self.printer.set_stats(stats);
+    }
+
+    /// Prints the blocks read from `reader`, keeping a batch of about 1 MiB of blocks in memory.
+    /// `block_len` gets the first `head_len` bytes of a block and returns its total length,
+    /// `styler` styles a batch of whole blocks starting at `StyleBuilder::offset`.
+    pub fn print<R, L, S>(self, reader: R, head_len: usize, block_len: L, mut styler: S)
+    where
+        R: Read,
+        L: FnMut(&[u8]) -> usize,
+        S: FnMut(StyleBuilder),
+    {
+        let mut blocks = 0;
+        for_each_batch(reader, head_len, self.batch_len, block_len, |offset, buf| {
+            let mut main = Segment::new(offset, Ty::Ascii, SegmentKind::Main);
+            styler(StyleBuilder::new(buf, offset, &mut main.childs));
+            self.printer.print_main(buf, &main, blocks);
+            blocks += self.printer.filter.count_blocks(buf, &main);
+        });
+    }
+}
+
+/// Prints blocks as an HTML page as soon as they are styled, see `TermStream`. The outline and
+/// the heatmap are left out, as they would grow with the file.
+pub struct HtmlStream {
+    printer: HtmlPrinter<&'static [u8]>,
+    batch_len: usize,
+    stats: bool,
+}
+
+impl HtmlStream {
+    pub fn new(layout: Layout, theme: Theme, filter: Filter) -> HtmlStream {
+        assert!(!layout.aligned, "Aligned rows can't be streamed");
+        let mut printer = HtmlPrinter::new(&[][..]);
+        printer.set_layout(layout);
+        printer.set_theme(theme);
+        printer.set_filter(filter);
+        HtmlStream { printer, batch_len: BATCH_LEN, stats: false }
+    }
+
+    /// See `HtmlPrinter::set_charset`
+    pub fn set_charset(&mut self, charset: CharSet) {
+        self.printer.set_charset(charset);
+    }
+
+    /// Show a sidebar with a histogram of all printed bytes
+    pub fn set_stats(&mut self, stats: bool) {
+        self.stats = stats;
+    }
+
+    /// See `TermStream::print`
+    pub fn print<R, L, S>(self, reader: R, head_len: usize, block_len: L, mut styler: S)
+    where
+        R: Read,
+        L: FnMut(&[u8]) -> usize,
+        S: FnMut(StyleBuilder),
+    {
+        let printer = &self.printer;
+        HtmlPrinter::<&[u8]>::print_head();
+        HtmlPrinter::<&[u8]>::print_outline("");
+        println!("<main>");
+        let mut blocks = 0;
+        let mut total = Stats::new(&[]);
+        for_each_batch(reader, head_len, self.batch_len, block_len, |offset, buf| {
+            let mut main = Segment::new(offset, Ty::Ascii, SegmentKind::Main);
+            styler(StyleBuilder::new(buf, offset, &mut main.childs));
+            printer.print_main(buf, &main, blocks);
+            if self.stats {
+                for (buf, _) in printer.filter.tops(buf, &main, blocks) {
+                    total.add(buf);
+                }
+            }
+            blocks += printer.filter.count_blocks(buf, &main);
+        });
+        println!("</main>");
+        if self.stats {
+            HtmlPrinter::<&[u8]>::print_stats("", &total);
+        }
+        HtmlPrinter::<&[u8]>::print_tail();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Blocks of the given lens, each starting with its len
+    fn blocks(lens: &[u8]) -> Vec<u8> {
+        lens.iter().flat_map(|&len| (0..len).map(move |i| if i == 0 { len } else { i })).collect()
+    }
+
+    fn batches(buf: &[u8], batch_len: usize) -> Vec<(usize, Vec<u8>)> {
+        let mut batches = Vec::new();
+        for_each_batch(buf, 1, batch_len, |head| head[0] as usize, |offset, batch| batches.push((offset, batch.to_vec())));
+        batches
+    }
+
+    #[test]
+    fn batch_boundaries() {
+        let buf = blocks(&[3, 2, 4, 1, 6, 2]);
+        let found = batches(&buf, 5);
+        let offsets = found.iter().map(|&(offset, ref batch)| (offset, batch.len())).collect::<Vec<_>>();
+        assert_eq!(offsets, vec![(0, 5), (5, 5), (10, 6), (16, 2)]);
+        // Every batch starts with a block and the batches make up the input
+        assert_eq!(found[1].1, blocks(&[4, 1]));
+        assert_eq!(found.iter().flat_map(|(_, batch)| batch.clone()).collect::<Vec<_>>(), buf);
+
+        assert_eq!(batches(&buf, 1).len(), 6);
+        assert_eq!(batches(&buf, 100), vec![(0, buf.clone())]);
+        assert_eq!(batches(&[], 5), vec![]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Truncated block head at offset 5")]
+    fn truncated_head() {
+        let mut buf = blocks(&[3, 2]);
+        buf.push(0);
+        for_each_batch(&buf[..], 2, 4, |head| head[0] as usize, |_, _| {});
+    }
+
+    #[test]
+    #[should_panic(expected = "Truncated block at offset 3")]
+    fn truncated_block() {
+        let buf = blocks(&[3, 4]);
+        batches(&buf[..5], 100);
+    }
+
+    #[test]
+    #[should_panic(expected = "Block len 0 at offset 3 is shorter than its head")]
+    fn empty_block() {
+        let mut buf = blocks(&[3]);
+        buf.push(0);
+        batches(&buf, 100);
+    }
+
+    #[test]
+    fn styled_at_offset() {
+        let buf = blocks(&[3, 2, 4]);
+        let mut styled = Vec::new();
+        let mut stream = TermStream::new(Layout::default(), Theme::default(), Filter::default());
+        stream.set_color_support(ColorSupport::None);
+        stream.batch_len = 4;
+        stream.print(&buf[..], 1, |head| head[0] as usize, |mut builder: StyleBuilder| {
+            styled.push((builder.offset(), builder.buf.len()));
+            let mut begin = 0;
+            while begin < builder.buf.len() {
+                let len = builder.buf[begin] as usize;
+                builder.block(begin, begin + len, Ty::Binary).line(1, Ty::BeNum, "len");
+                begin += len;
+            }
+        });
+        assert_eq!(styled, vec![(0, 5), (5, 4)]);
+    }
+}
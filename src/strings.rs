@@ -0,0 +1,136 @@
+//! Styler for unknown data which marks the text in it, like `strings`.
+
+use std::char;
+use std::str;
+
+use {Color, Encoding, Style, StyleBuilder, Ty};
+
+fn is_printable(c: char) -> bool {
+    !c.is_control() || c == '\t' || c == '\n' || c == '\r'
+}
+
+/// Length in bytes and chars of the printable UTF-8 text at the start of `buf`
+fn utf8_run(buf: &[u8]) -> (usize, usize) {
+    let (mut len, mut chars) = (0, 0);
+    while len < buf.len() {
+        let size = match buf[len] {
+            0x00..=0x7F => 1,
+            0xC0..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF7 => 4,
+            _ => break,
+        };
+        let c = buf.get(len..len + size).and_then(|bytes| str::from_utf8(bytes).ok()).and_then(|s| s.chars().next());
+        match c {
+            Some(c) if is_printable(c) && !(chars == 0 && c.is_whitespace()) => {
+                len += size;
+                chars += 1;
+            }
+            _ => break,
+        }
+    }
+    (len, chars)
+}
+
+/// Length in bytes and chars of the printable UTF-16 text at the start of `buf`. Only ASCII
+/// chars are accepted, as nearly every pair of bytes is a valid UTF-16 char.
+fn utf16_run(buf: &[u8], encoding: Encoding) -> (usize, usize) {
+    let units = buf.chunks_exact(2).map(|unit| match encoding {
+        Encoding::Utf16Le => unit[0] as u16 | (unit[1] as u16) << 8,
+        _ => (unit[0] as u16) << 8 | unit[1] as u16,
+    });
+    let chars = units
+        .map(|unit| char::from_u32(unit as u32))
+        .enumerate()
+        .take_while(|&(i, c)| c.is_some_and(|c| c.is_ascii() && is_printable(c) && !(i == 0 && c.is_whitespace())))
+        .count();
+    (chars * 2, chars)
+}
+
+/// Styles text of at least `min_len` chars in UTF-8 or ASCII only UTF-16 as string lines and the
+/// bytes between them as binary lines. Strings don't start with whitespace. The longest candidate
+/// wins when texts in several encodings start at the same byte, except that UTF-16BE text
+/// followed by UTF-16LE text of as many chars one byte later is read as the latter.
+pub fn style_strings(mut builder: StyleBuilder, min_len: usize) {
+    let buf = builder.buf;
+    let mut pos = 0;
+    while pos < buf.len() {
+        let candidates = [
+            (utf8_run(&buf[pos..]), Encoding::Utf8),
+            (utf16_run(&buf[pos..], Encoding::Utf16Le), Encoding::Utf16Le),
+            (utf16_run(&buf[pos..], Encoding::Utf16Be), Encoding::Utf16Be),
+        ];
+        let found = candidates.iter().filter(|&&((_, chars), _)| chars >= min_len.max(1)).max_by_key(|&&((len, _), _)| len);
+        let (len, encoding) = match found {
+            // UTF-16LE text after a zero byte also reads as UTF-16BE text starting at the zero
+            Some(&((_, chars), Encoding::Utf16Be)) if utf16_run(&buf[pos + 1..], Encoding::Utf16Le).1 >= chars => {
+                pos += 1;
+                continue;
+            }
+            Some(&((len, _), encoding)) => (len, encoding),
+            None => {
+                pos += 1;
+                continue;
+            }
+        };
+        if builder.index() < pos {
+            builder.set_style(Style::fg(Color::White).dim());
+            builder.line_until(pos, Ty::Binary, "data");
+        }
+        builder.set_color(Color::Magenta);
+        builder.line(len, Ty::Text(encoding), "string");
+        pos += len;
+    }
+    if builder.index() < buf.len() {
+        builder.set_style(Style::fg(Color::White).dim());
+        builder.line_until(buf.len(), Ty::Binary, "data");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {Segment, SegmentKind, SegmentRef};
+
+    fn strings(buf: &[u8], min_len: usize) -> Vec<(usize, usize, String, Option<String>)> {
+        let mut main = Segment::new(0, Ty::Binary, SegmentKind::Main);
+        style_strings(StyleBuilder::new(buf, 0, &mut main.childs), min_len);
+        let root = SegmentRef::new(buf, &main);
+        let strings = root.childs().map(|seg| (seg.start(), seg.end(), seg.name().to_string(), seg.value()));
+        strings.collect()
+    }
+
+    #[test]
+    fn runs() {
+        assert_eq!(utf8_run(b"h\xC3\xA9llo\x00"), (6, 5));
+        assert_eq!(utf8_run(b" hello"), (0, 0));
+        assert_eq!(utf8_run(b"ab\xC3"), (2, 2));
+        assert_eq!(utf16_run(b"h\x00i\x00\x01\x00", Encoding::Utf16Le), (4, 2));
+        assert_eq!(utf16_run(b"\x00h\x00i\x00", Encoding::Utf16Be), (4, 2));
+        assert_eq!(utf16_run(b"\x00h", Encoding::Utf16Le), (0, 0));
+    }
+
+    #[test]
+    fn utf8_and_gaps() {
+        let found = strings(b"\x01\x02hello\x00ab\x00world", 4);
+        assert_eq!(found, vec![
+            (0, 2, "data".to_string(), None),
+            (2, 7, "string".to_string(), Some("hello".to_string())),
+            (7, 11, "data".to_string(), None),
+            (11, 16, "string".to_string(), Some("world".to_string())),
+        ]);
+    }
+
+    #[test]
+    fn utf16le_after_zero() {
+        let found = strings(b"\x00\x00H\x00i\x00 \x00t\x00h\x00e\x00r\x00e\x00\x01", 4);
+        assert_eq!(found, vec![
+            (0, 2, "data".to_string(), None),
+            (2, 18, "string".to_string(), Some("Hi there".to_string())),
+            (18, 19, "data".to_string(), None),
+        ]);
+        // The UTF-16LE text one byte later is shorter
+        let found = strings(b"\x01\x00H\x00i\x00!\x00!", 4);
+        assert_eq!(found[1], (1, 9, "string".to_string(), Some("Hi!!".to_string())));
+    }
+}
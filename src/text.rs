@@ -0,0 +1,325 @@
+//! Decoding of text in the encodings supported by `Ty::Text`.
+
+use std::char;
+use std::iter;
+
+#[derive(Copy, Clone)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+    /// EBCDIC code page 037
+    Ebcdic,
+}
+
+impl Encoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "utf8",
+            Encoding::Utf16Le => "utf16le",
+            Encoding::Utf16Be => "utf16be",
+            Encoding::Latin1 => "latin1",
+            Encoding::Ebcdic => "ebcdic",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Encoding> {
+        match name {
+            "utf8" => Some(Encoding::Utf8),
+            "utf16le" => Some(Encoding::Utf16Le),
+            "utf16be" => Some(Encoding::Utf16Be),
+            "latin1" => Some(Encoding::Latin1),
+            "ebcdic" => Some(Encoding::Ebcdic),
+            _ => None,
+        }
+    }
+
+    /// Encodes `text`, `None` if it contains a char the encoding can't represent
+    pub fn encode(self, text: &str) -> Option<Vec<u8>> {
+        match self {
+            Encoding::Utf8 => Some(text.as_bytes().to_vec()),
+            Encoding::Utf16Le => Some(text.encode_utf16().flat_map(u16::to_le_bytes).collect()),
+            Encoding::Utf16Be => Some(text.encode_utf16().flat_map(u16::to_be_bytes).collect()),
+            Encoding::Latin1 => text.chars().map(|c| if (c as u32) < 0x100 { Some(c as u8) } else { None }).collect(),
+            Encoding::Ebcdic => text.chars().map(|c| EBCDIC.iter().position(|&e| e == c).map(|b| b as u8)).collect(),
+        }
+    }
+
+    /// Size of a single code unit. A NUL terminator is one code unit long.
+    pub fn unit_len(self) -> usize {
+        match self {
+            Encoding::Utf16Le | Encoding::Utf16Be => 2,
+            _ => 1,
+        }
+    }
+
+    /// Decodes the whole buffer. Trailing NUL characters are stripped.
+    pub fn decode(self, buf: &[u8]) -> String {
+        let text = match self {
+            Encoding::Utf8 => String::from_utf8_lossy(buf).into_owned(),
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let units = buf.chunks(2).map(|unit| self.read_unit(unit)).collect::<Vec<_>>();
+                String::from_utf16_lossy(&units)
+            }
+            Encoding::Latin1 => buf.iter().map(|&b| b as char).collect(),
+            Encoding::Ebcdic => buf.iter().map(|&b| EBCDIC[b as usize]).collect(),
+        };
+        text.trim_end_matches('\0').to_string()
+    }
+
+    /// Decodes one char per byte of the first `len` bytes of `buf` for the text column. A char
+    /// is shown at the first byte it is made of and the other bytes are shown as `·`. Bytes
+    /// after `len` are only used to complete a char which starts before `len`.
+    pub fn cells(self, buf: &[u8], len: usize) -> Vec<char> {
+        let mut cells = Vec::with_capacity(len);
+        while cells.len() < len {
+            let i = cells.len();
+            let (c, size) = match self {
+                Encoding::Utf8 => decode_utf8(&buf[i..], i < 3 && buf[..i].iter().all(|&b| is_continuation(b))),
+                Encoding::Utf16Le | Encoding::Utf16Be => self.decode_utf16(&buf[i..]),
+                Encoding::Latin1 => (Some(buf[i] as char), 1),
+                Encoding::Ebcdic => (Some(EBCDIC[buf[i] as usize]), 1),
+            };
+            cells.push(c.map_or('\u{FFFD}', printable));
+            cells.extend(iter::repeat_n('·', size - 1));
+        }
+        cells.truncate(len);
+        cells
+    }
+
+    fn read_unit(self, unit: &[u8]) -> u16 {
+        match (self, unit.len()) {
+            (_, 1) => 0xFFFD,
+            (Encoding::Utf16Le, _) => unit[0] as u16 | (unit[1] as u16) << 8,
+            _ => (unit[0] as u16) << 8 | unit[1] as u16,
+        }
+    }
+
+    fn decode_utf16(self, buf: &[u8]) -> (Option<char>, usize) {
+        if buf.len() < 2 {
+            return (None, 1);
+        }
+        let units = buf.chunks(2).take(2).map(|unit| self.read_unit(unit));
+        match char::decode_utf16(units).next() {
+            Some(Ok(c)) => (Some(c), c.len_utf16() * 2),
+            _ => (None, 2),
+        }
+    }
+}
+
+/// Rendering of the bytes in the text column
+#[derive(Copy, Clone)]
+pub enum CharSet {
+    /// Printable ASCII only
+    Ascii,
+    /// The IBM PC character set, which has a glyph for every byte
+    Cp437,
+    Latin1,
+    /// EBCDIC code page 037
+    Ebcdic,
+    /// Multibyte chars are shown at their first byte
+    Utf8,
+}
+
+impl CharSet {
+    pub fn from_name(name: &str) -> Option<CharSet> {
+        match name {
+            "ascii" => Some(CharSet::Ascii),
+            "cp437" => Some(CharSet::Cp437),
+            "latin1" => Some(CharSet::Latin1),
+            "ebcdic" => Some(CharSet::Ebcdic),
+            "utf8" => Some(CharSet::Utf8),
+            _ => None,
+        }
+    }
+
+    /// See `Encoding::cells`
+    pub fn cells(self, buf: &[u8], len: usize) -> Vec<char> {
+        match self {
+            CharSet::Ascii => buf[..len]
+                .iter()
+                .map(|&b| if b < 0x80 { printable(b as char) } else { '\u{FFFD}' })
+                .collect(),
+            CharSet::Cp437 => buf[..len].iter().map(|&b| CP437[b as usize]).collect(),
+            CharSet::Latin1 => Encoding::Latin1.cells(buf, len),
+            CharSet::Ebcdic => Encoding::Ebcdic.cells(buf, len),
+            CharSet::Utf8 => Encoding::Utf8.cells(buf, len),
+        }
+    }
+}
+
+fn is_continuation(b: u8) -> bool {
+    b & 0xC0 == 0x80
+}
+
+/// Continuation bytes at the start of a row are assumed to belong to a char of the row before,
+/// `at_start` is set for them. Other continuation bytes which don't follow a lead byte are
+/// invalid.
+fn decode_utf8(buf: &[u8], at_start: bool) -> (Option<char>, usize) {
+    let size = match buf[0] {
+        0x00..=0x7F => 1,
+        0x80..=0xBF if at_start => return (Some('·'), 1),
+        0x80..=0xBF => return (None, 1),
+        0xC0..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        _ => 4,
+    };
+    match buf.get(..size).and_then(|bytes| ::std::str::from_utf8(bytes).ok()) {
+        Some(s) => (s.chars().next(), size),
+        None => (None, 1),
+    }
+}
+
+/// Replaces control chars, which would mess up the output, with a visible char.
+pub fn printable(c: char) -> char {
+    match c {
+        '\n' => '␊',
+        '\r' => '␍',
+        '\0' => '␀',
+        _ if c.is_control() => '\u{FFFD}',
+        _ => c,
+    }
+}
+
+static EBCDIC: [char; 256] = [
+    '\u{0}', '\u{1}', '\u{2}', '\u{3}', '\u{9c}', '\u{9}', '\u{86}', '\u{7f}',
+    '\u{97}', '\u{8d}', '\u{8e}', '\u{b}', '\u{c}', '\u{d}', '\u{e}', '\u{f}',
+    '\u{10}', '\u{11}', '\u{12}', '\u{13}', '\u{9d}', '\u{85}', '\u{8}', '\u{87}',
+    '\u{18}', '\u{19}', '\u{92}', '\u{8f}', '\u{1c}', '\u{1d}', '\u{1e}', '\u{1f}',
+    '\u{80}', '\u{81}', '\u{82}', '\u{83}', '\u{84}', '\u{a}', '\u{17}', '\u{1b}',
+    '\u{88}', '\u{89}', '\u{8a}', '\u{8b}', '\u{8c}', '\u{5}', '\u{6}', '\u{7}',
+    '\u{90}', '\u{91}', '\u{16}', '\u{93}', '\u{94}', '\u{95}', '\u{96}', '\u{4}',
+    '\u{98}', '\u{99}', '\u{9a}', '\u{9b}', '\u{14}', '\u{15}', '\u{9e}', '\u{1a}',
+    ' ', '\u{a0}', 'â', 'ä', 'à', 'á', 'ã', 'å',
+    'ç', 'ñ', '¢', '.', '<', '(', '+', '|',
+    '&', 'é', 'ê', 'ë', 'è', 'í', 'î', 'ï',
+    'ì', 'ß', '!', '$', '*', ')', ';', '¬',
+    '-', '/', 'Â', 'Ä', 'À', 'Á', 'Ã', 'Å',
+    'Ç', 'Ñ', '¦', ',', '%', '_', '>', '?',
+    'ø', 'É', 'Ê', 'Ë', 'È', 'Í', 'Î', 'Ï',
+    'Ì', '`', ':', '#', '@', '\'', '=', '"',
+    'Ø', 'a', 'b', 'c', 'd', 'e', 'f', 'g',
+    'h', 'i', '«', '»', 'ð', 'ý', 'þ', '±',
+    '°', 'j', 'k', 'l', 'm', 'n', 'o', 'p',
+    'q', 'r', 'ª', 'º', 'æ', '¸', 'Æ', '¤',
+    'µ', '~', 's', 't', 'u', 'v', 'w', 'x',
+    'y', 'z', '¡', '¿', 'Ð', 'Ý', 'Þ', '®',
+    '^', '£', '¥', '·', '©', '§', '¶', '¼',
+    '½', '¾', '[', ']', '¯', '¨', '´', '×',
+    '{', 'A', 'B', 'C', 'D', 'E', 'F', 'G',
+    'H', 'I', '\u{ad}', 'ô', 'ö', 'ò', 'ó', 'õ',
+    '}', 'J', 'K', 'L', 'M', 'N', 'O', 'P',
+    'Q', 'R', '¹', 'û', 'ü', 'ù', 'ú', 'ÿ',
+    '\\', '÷', 'S', 'T', 'U', 'V', 'W', 'X',
+    'Y', 'Z', '²', 'Ô', 'Ö', 'Ò', 'Ó', 'Õ',
+    '0', '1', '2', '3', '4', '5', '6', '7',
+    '8', '9', '³', 'Û', 'Ü', 'Ù', 'Ú', '\u{9f}',
+];
+
+static CP437: [char; 256] = [
+    ' ', '☺', '☻', '♥', '♦', '♣', '♠', '•',
+    '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼',
+    '►', '◄', '↕', '‼', '¶', '§', '▬', '↨',
+    '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+    ' ', '!', '"', '#', '$', '%', '&', '\'',
+    '(', ')', '*', '+', ',', '-', '.', '/',
+    '0', '1', '2', '3', '4', '5', '6', '7',
+    '8', '9', ':', ';', '<', '=', '>', '?',
+    '@', 'A', 'B', 'C', 'D', 'E', 'F', 'G',
+    'H', 'I', 'J', 'K', 'L', 'M', 'N', 'O',
+    'P', 'Q', 'R', 'S', 'T', 'U', 'V', 'W',
+    'X', 'Y', 'Z', '[', '\\', ']', '^', '_',
+    '`', 'a', 'b', 'c', 'd', 'e', 'f', 'g',
+    'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o',
+    'p', 'q', 'r', 's', 't', 'u', 'v', 'w',
+    'x', 'y', 'z', '{', '|', '}', '~', '⌂',
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç',
+    'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù',
+    'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º',
+    '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖',
+    '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟',
+    '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫',
+    '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ',
+    'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈',
+    '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {Endian, Segment, SegmentKind, StrLen, StyleBuilder, Ty};
+
+    #[test]
+    fn utf8_continuation() {
+        let cells = |buf: &[u8]| Encoding::Utf8.cells(buf, buf.len()).into_iter().collect::<String>();
+        assert_eq!(cells(b"\x82\x82A\xE2\x82\xACB"), "··A€··B");
+        assert_eq!(cells(b"A\x82B\xC3"), "A\u{FFFD}B\u{FFFD}");
+    }
+
+    #[test]
+    fn utf16() {
+        assert_eq!(Encoding::Utf16Le.encode("hi€"), Some(vec![0x68, 0x00, 0x69, 0x00, 0xAC, 0x20]));
+        assert_eq!(Encoding::Utf16Be.encode("hi€"), Some(vec![0x00, 0x68, 0x00, 0x69, 0x20, 0xAC]));
+        assert_eq!(Encoding::Utf16Le.decode(&[0x68, 0x00, 0x3D, 0xD8, 0x00, 0xDE, 0x00, 0x00]), "h😀");
+        assert_eq!(Encoding::Utf16Be.decode(&[0x00, 0x68, 0x00]), "h\u{FFFD}");
+        let cells = Encoding::Utf16Le.cells(&[0x3D, 0xD8, 0x00, 0xDE, 0x68, 0x00], 6);
+        assert_eq!(cells.into_iter().collect::<String>(), "😀···h·");
+    }
+
+    #[test]
+    fn ebcdic() {
+        assert_eq!(Encoding::Ebcdic.encode("Hi 0"), Some(vec![0xC8, 0x89, 0x40, 0xF0]));
+        assert_eq!(Encoding::Ebcdic.decode(&[0xC8, 0x89, 0x40, 0xF0]), "Hi 0");
+        assert_eq!(Encoding::Ebcdic.encode("€"), None);
+        assert_eq!(Encoding::Latin1.encode("é"), Some(vec![0xE9]));
+        assert_eq!(Encoding::Latin1.encode("€"), None);
+    }
+
+    /// Styles a string at the start of `buf` and returns its text and the lines
+    fn string(buf: &[u8], len: StrLen, encoding: Encoding) -> (String, Vec<(usize, usize, String)>) {
+        let mut main = Segment::new(0, Ty::Binary, SegmentKind::Main);
+        let text = StyleBuilder::new(buf, 0, &mut main.childs).string(len, encoding, "name");
+        let lines = main.childs.iter().map(|(&(begin, end), seg)| match seg.kind {
+            SegmentKind::Line { ref tag, .. } => (begin, end, tag.clone()),
+            _ => unreachable!(),
+        });
+        (text, lines.collect())
+    }
+
+    #[test]
+    fn nul_terminated() {
+        let (text, lines) = string(b"abc\0def\0", StrLen::NulTerminated, Encoding::Utf8);
+        assert_eq!((text.as_str(), lines), ("abc", vec![(0, 4, "name".to_string())]));
+        // The zero bytes of U+0100 don't end the string, only a whole zero code unit does
+        let (text, lines) = string(&[0x68, 0x00, 0x00, 0x01, 0x00, 0x00, 0x41], StrLen::NulTerminated, Encoding::Utf16Le);
+        assert_eq!((text.as_str(), lines), ("hĀ", vec![(0, 6, "name".to_string())]));
+    }
+
+    #[test]
+    #[should_panic(expected = "Missing NUL terminator for string at index 0")]
+    fn missing_terminator() {
+        string(&[0x68, 0x00, 0x00], StrLen::NulTerminated, Encoding::Utf16Le);
+    }
+
+    #[test]
+    fn prefixed() {
+        let (text, lines) = string(b"\x00\x03abcd", StrLen::Prefixed(2, Endian::Big), Encoding::Utf8);
+        assert_eq!((text.as_str(), lines), ("abc", vec![(0, 2, "name len".to_string()), (2, 5, "name".to_string())]));
+        let (text, _) = string(b"\x04\x00\x00\x00h\x00i\x00", StrLen::Prefixed(4, Endian::Little), Encoding::Utf16Le);
+        assert_eq!(text, "hi");
+    }
+
+    #[test]
+    #[should_panic(expected = "Too big len 5 exceeds buf len 4 (index 1)")]
+    fn prefixed_too_long() {
+        string(b"\x05abc", StrLen::Prefixed(1, Endian::Big), Encoding::Utf8);
+    }
+}
@@ -0,0 +1,270 @@
+//! Colors, text attributes and the mapping of semantic roles to them.
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Color {
+    Black,
+    Blue,
+    Cyan,
+    Green,
+    Magenta,
+    Red,
+    Yellow,
+    White,
+    /// Index into the 256 color palette of xterm
+    Fixed(u8),
+    Rgb(u8, u8, u8),
+}
+
+const BASIC: [(Color, (u8, u8, u8)); 8] = [
+    (Color::Black, (0, 0, 0)),
+    (Color::Red, (205, 0, 0)),
+    (Color::Green, (0, 205, 0)),
+    (Color::Yellow, (205, 205, 0)),
+    (Color::Blue, (0, 0, 238)),
+    (Color::Magenta, (205, 0, 205)),
+    (Color::Cyan, (0, 205, 205)),
+    (Color::White, (229, 229, 229)),
+];
+
+const BRIGHT: [(u8, u8, u8); 8] = [
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+fn cube_index(v: u8) -> u8 {
+    match v {
+        0..=47 => 0,
+        48..=114 => 1,
+        _ => (v - 35) / 40,
+    }
+}
+
+impl Color {
+    /// Approximate rgb value, using the default xterm palette for the basic and fixed colors
+    pub fn rgb(self) -> (u8, u8, u8) {
+        match self {
+            Color::Rgb(r, g, b) => (r, g, b),
+            Color::Fixed(n @ 0..=7) => BASIC[n as usize].1,
+            Color::Fixed(n @ 8..=15) => BRIGHT[n as usize - 8],
+            Color::Fixed(n @ 16..=231) => {
+                let n = n - 16;
+                (CUBE_LEVELS[n as usize / 36], CUBE_LEVELS[n as usize / 6 % 6], CUBE_LEVELS[n as usize % 6])
+            }
+            Color::Fixed(n) => {
+                let gray = 8 + 10 * (n - 232);
+                (gray, gray, gray)
+            }
+            basic => BASIC.iter().find(|&&(color, _)| color == basic).unwrap().1,
+        }
+    }
+
+    /// Nearest color which can be shown with `support`
+    pub fn downgrade(self, support: ColorSupport) -> Color {
+        match (self, support) {
+            (Color::Rgb(..), ColorSupport::TrueColor) | (Color::Fixed(_), ColorSupport::TrueColor) => self,
+            (Color::Rgb(r, g, b), ColorSupport::Ansi256) => {
+                Color::Fixed(16 + 36 * cube_index(r) + 6 * cube_index(g) + cube_index(b))
+            }
+            (Color::Fixed(_), ColorSupport::Ansi256) => self,
+            (Color::Rgb(..), ColorSupport::Basic) | (Color::Fixed(_), ColorSupport::Basic) => {
+                let (r, g, b) = self.rgb();
+                let distance = |&&(_, (r2, g2, b2)): &&(Color, (u8, u8, u8))| {
+                    let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+                    d(r, r2) + d(g, g2) + d(b, b2)
+                };
+                BASIC.iter().min_by_key(distance).unwrap().0
+            }
+            (color, _) => color,
+        }
+    }
+}
+
+/// Colors a terminal can show
+#[derive(Copy, Clone, PartialEq)]
+pub enum ColorSupport {
+    /// Plain text without any escape codes
+    None,
+    /// The 8 basic colors
+    Basic,
+    Ansi256,
+    TrueColor,
+}
+
+impl ColorSupport {
+    /// Guess from `COLORTERM` and `TERM` like most terminal programs do. Colors are disabled when
+    /// stdout is not a terminal or `NO_COLOR` is set.
+    pub fn detect() -> ColorSupport {
+        let no_color = ::std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+        if no_color || !::termion::is_tty(&::std::io::stdout()) {
+            return ColorSupport::None;
+        }
+        let colorterm = ::std::env::var("COLORTERM").unwrap_or_default();
+        let term = ::std::env::var("TERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            ColorSupport::TrueColor
+        } else if term.contains("256color") {
+            ColorSupport::Ansi256
+        } else {
+            ColorSupport::Basic
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ColorSupport> {
+        match name {
+            "none" => Some(ColorSupport::None),
+            "basic" => Some(ColorSupport::Basic),
+            "256" => Some(ColorSupport::Ansi256),
+            "truecolor" => Some(ColorSupport::TrueColor),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub struct Style {
+    pub fg: Color,
+    pub bg: Option<Color>,
+    pub bold: bool,
+    pub underline: bool,
+    pub dim: bool,
+}
+
+impl Style {
+    pub fn fg(color: Color) -> Style {
+        Style {
+            fg: color,
+            bg: None,
+            bold: false,
+            underline: false,
+            dim: false,
+        }
+    }
+
+    pub fn on(self, bg: Color) -> Style {
+        Style { bg: Some(bg), ..self }
+    }
+
+    pub fn bold(self) -> Style {
+        Style { bold: true, ..self }
+    }
+
+    pub fn underline(self) -> Style {
+        Style { underline: true, ..self }
+    }
+
+    pub fn dim(self) -> Style {
+        Style { dim: true, ..self }
+    }
+}
+
+/// Meaning of a segment, mapped to a style by the `Theme` of the printer
+#[derive(Copy, Clone, PartialEq)]
+pub enum Role {
+    Header,
+    Length,
+    Checksum,
+    Payload,
+    /// Also used for unknown enum values and flags
+    Error,
+}
+
+/// How a line is styled: either directly or by its role
+#[derive(Copy, Clone, PartialEq)]
+pub enum Paint {
+    Style(Style),
+    Role(Role),
+}
+
+#[derive(Clone)]
+pub struct Theme {
+    pub header: Style,
+    pub length: Style,
+    pub checksum: Style,
+    pub payload: Style,
+    pub error: Style,
+    /// Background of rows containing marked bytes
+    pub mark: Color,
+    /// Bytes only in the old or the new file of a diff
+    pub removed: Style,
+    pub added: Style,
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        Theme {
+            header: Style::fg(Color::Rgb(0x5f, 0xaf, 0xff)).bold(),
+            length: Style::fg(Color::Fixed(110)),
+            checksum: Style::fg(Color::Fixed(244)).underline(),
+            payload: Style::fg(Color::White).dim(),
+            error: Style::fg(Color::Red).bold(),
+            mark: Color::Fixed(58),
+            removed: Style::fg(Color::Black).on(Color::Red),
+            added: Style::fg(Color::Black).on(Color::Green),
+        }
+    }
+}
+
+impl Theme {
+    pub fn style(&self, role: Role) -> Style {
+        match role {
+            Role::Header => self.header,
+            Role::Length => self.length,
+            Role::Checksum => self.checksum,
+            Role::Payload => self.payload,
+            Role::Error => self.error,
+        }
+    }
+
+    pub fn resolve(&self, paint: Paint) -> Style {
+        match paint {
+            Paint::Style(style) => style,
+            Paint::Role(role) => self.style(role),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cube() {
+        let levels = [0, 47, 48, 114, 115, 154, 155, 194, 195, 234, 235, 255];
+        assert_eq!(levels.map(cube_index), [0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5]);
+        assert_eq!(Color::Rgb(0x5f, 0xaf, 0xff).downgrade(ColorSupport::Ansi256), Color::Fixed(75));
+        assert_eq!(Color::Rgb(0, 0, 0).downgrade(ColorSupport::Ansi256), Color::Fixed(16));
+        assert_eq!(Color::Rgb(255, 255, 255).downgrade(ColorSupport::Ansi256), Color::Fixed(231));
+        assert_eq!(Color::Fixed(75).rgb(), (0x5f, 0xaf, 0xff));
+        assert_eq!(Color::Fixed(196).rgb(), (255, 0, 0));
+    }
+
+    #[test]
+    fn palette() {
+        assert_eq!(Color::Fixed(3).rgb(), BASIC[3].1);
+        assert_eq!(Color::Fixed(9).rgb(), BRIGHT[1]);
+        assert_eq!(Color::Fixed(232).rgb(), (8, 8, 8));
+        assert_eq!(Color::Fixed(255).rgb(), (238, 238, 238));
+        assert_eq!(Color::Blue.rgb(), (0, 0, 238));
+    }
+
+    #[test]
+    fn downgrade() {
+        assert_eq!(Color::Rgb(250, 0, 0).downgrade(ColorSupport::Basic), Color::Red);
+        assert_eq!(Color::Fixed(232).downgrade(ColorSupport::Basic), Color::Black);
+        assert_eq!(Color::Fixed(255).downgrade(ColorSupport::Basic), Color::White);
+        assert_eq!(Color::Fixed(12).downgrade(ColorSupport::Basic), Color::Blue);
+        // Colors the terminal supports are kept
+        assert_eq!(Color::Fixed(75).downgrade(ColorSupport::Ansi256), Color::Fixed(75));
+        assert_eq!(Color::Rgb(1, 2, 3).downgrade(ColorSupport::TrueColor), Color::Rgb(1, 2, 3));
+        assert_eq!(Color::Red.downgrade(ColorSupport::Ansi256), Color::Red);
+        assert_eq!(Color::Rgb(1, 2, 3).downgrade(ColorSupport::None), Color::Rgb(1, 2, 3));
+    }
+}
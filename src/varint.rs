@@ -0,0 +1,93 @@
+//! Decoders for variable-length integer encodings.
+//!
+//! Every decoder returns the decoded value together with the number of bytes
+//! it occupies, or `None` when the buffer ends before the last byte of the
+//! integer. Bits which don't fit in 64 bits are dropped.
+
+/// Unsigned LEB128 as used by DWARF, WebAssembly and protobuf varints.
+pub fn read_uleb128(buf: &[u8]) -> Option<(u64, usize)> {
+    let mut value = 0u64;
+    for (i, &b) in buf.iter().enumerate() {
+        if i < 10 {
+            value |= ((b & 0x7f) as u64) << (7 * i);
+        }
+        if b & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+/// Signed LEB128 as used by DWARF and WebAssembly.
+pub fn read_sleb128(buf: &[u8]) -> Option<(i64, usize)> {
+    let mut value = 0i64;
+    for (i, &b) in buf.iter().enumerate() {
+        let shift = 7 * i;
+        if shift < 64 {
+            value |= ((b & 0x7f) as i64) << shift;
+        }
+        if b & 0x80 == 0 {
+            if shift + 7 < 64 && b & 0x40 != 0 {
+                value |= -1i64 << (shift + 7);
+            }
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+/// Protobuf `sint32`/`sint64`: an unsigned LEB128 holding a zigzag encoded value.
+pub fn read_zigzag(buf: &[u8]) -> Option<(i64, usize)> {
+    read_uleb128(buf).map(|(value, len)| (((value >> 1) as i64) ^ -((value & 1) as i64), len))
+}
+
+/// Big endian base 128 as used by MIDI and ASN.1 object identifiers.
+pub fn read_vlq(buf: &[u8]) -> Option<(u64, usize)> {
+    let mut value = 0u64;
+    for (i, &b) in buf.iter().enumerate() {
+        value = (value << 7) | (b & 0x7f) as u64;
+        if b & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uleb128() {
+        assert_eq!(read_uleb128(&[0x00]), Some((0, 1)));
+        assert_eq!(read_uleb128(&[0xE5, 0x8E, 0x26, 0xFF]), Some((624485, 3)));
+        assert_eq!(read_uleb128(&[0xE5, 0x8E]), None);
+        assert_eq!(read_uleb128(&[]), None);
+    }
+
+    #[test]
+    fn sleb128() {
+        assert_eq!(read_sleb128(&[0x7F]), Some((-1, 1)));
+        assert_eq!(read_sleb128(&[0xC0, 0xBB, 0x78]), Some((-123456, 3)));
+        assert_eq!(read_sleb128(&[0xC0, 0xBB]), None);
+    }
+
+    #[test]
+    fn overflow() {
+        let max = [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F];
+        assert_eq!(read_uleb128(&max), Some((u64::MAX, 10)));
+        assert_eq!(read_sleb128(&max), Some((-1, 10)));
+        // Bits after the 10th byte are dropped
+        let long = [0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x01];
+        assert_eq!(read_uleb128(&long), Some((0, 11)));
+        assert_eq!(read_sleb128(&long), Some((0, 11)));
+    }
+
+    #[test]
+    fn zigzag_and_vlq() {
+        assert_eq!(read_zigzag(&[0x03]), Some((-2, 1)));
+        assert_eq!(read_zigzag(&[0x04]), Some((2, 1)));
+        assert_eq!(read_vlq(&[0x81, 0x00]), Some((128, 2)));
+        assert_eq!(read_vlq(&[0x81]), None);
+    }
+}